@@ -0,0 +1,189 @@
+//! Compares parallel indexing (`IndexConfig::index`) and the sequential
+//! `calculate_ignored_size` against a sequential `walkdir` traversal on a
+//! generated fixture tree, measures the effect of the parallel threshold on a
+//! tree of many small directories, compares the recursive and flat size
+//! strategies on evenly spread and on lopsided trees, and compares
+//! `file_size` (a size-only `statx` on Linux) with `fs::metadata`.
+//!
+//! The fixture size can be tuned with environment variables:
+//! - `FS_INDEX_BENCH_DIRS`: number of directories (default 500)
+//! - `FS_INDEX_BENCH_FILES`: files per directory (default 20)
+//!
+//! Run with `cargo bench --bench index`.
+
+use std::fs;
+use std::hint::black_box;
+use std::path::{Path, PathBuf};
+use criterion::{criterion_group, criterion_main, Criterion};
+use fs_index::{
+    calculate_ignored_size, file_size, IgnoredSizePolicy, IndexConfig, SizeStrategy,
+    DEFAULT_PARALLEL_THRESHOLD,
+};
+use walkdir::WalkDir;
+
+/// Directories are laid out with this many subdirectories per parent, giving
+/// a tree that is both wide and reasonably deep.
+const FANOUT: usize = 8;
+
+fn env_or(name: &str, default: usize) -> usize {
+    std::env::var(name)
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(default)
+}
+
+fn create_fixture(name: &str, dirs: usize, files_per_dir: usize) -> PathBuf {
+    let root = std::env::temp_dir().join(format!("fs-index-bench-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&root);
+
+    let mut paths = vec![root.clone()];
+    for i in 1..dirs {
+        let parent = paths[(i - 1) / FANOUT].clone();
+        paths.push(parent.join(format!("dir_{}", i)));
+    }
+
+    for (i, dir) in paths.iter().enumerate() {
+        fs::create_dir_all(dir).unwrap();
+        for j in 0..files_per_dir {
+            let contents = vec![b'x'; (i * 31 + j * 17) % 4096];
+            fs::write(dir.join(format!("file_{}.txt", j)), contents).unwrap();
+        }
+    }
+
+    root
+}
+
+fn walkdir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter_map(|entry| entry.metadata().ok())
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| metadata.len())
+        .sum()
+}
+
+fn bench_index(c: &mut Criterion) {
+    let dirs = env_or("FS_INDEX_BENCH_DIRS", 500);
+    let files_per_dir = env_or("FS_INDEX_BENCH_FILES", 20);
+    let root = create_fixture("index", dirs, files_per_dir);
+
+    let config = IndexConfig::new().parent_ignore(false);
+    let mut group = c.benchmark_group(format!("index_{}x{}", dirs, files_per_dir));
+    group.bench_function("index_folder", |b| {
+        b.iter(|| config.index(black_box(&root)).unwrap())
+    });
+    group.bench_function("walkdir_sequential", |b| {
+        b.iter(|| walkdir_size(black_box(&root)))
+    });
+    // Sequential like walkdir, but stats only files: directory types come
+    // from the listing.
+    group.bench_function("calculate_ignored_size", |b| {
+        b.iter(|| calculate_ignored_size(black_box(&root), IgnoredSizePolicy::SumAll).unwrap())
+    });
+    group.finish();
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// A typical source repository: many directories holding a handful of files,
+/// where engaging rayon for every directory costs more than it saves.
+fn bench_parallel_threshold(c: &mut Criterion) {
+    let dirs = env_or("FS_INDEX_BENCH_DIRS", 500) * 4;
+    let root = create_fixture("small-dirs", dirs, 3);
+
+    let mut group = c.benchmark_group(format!("small_dirs_{}x3", dirs));
+    for threshold in [0, DEFAULT_PARALLEL_THRESHOLD, usize::MAX] {
+        let label = match threshold {
+            0 => "always_parallel".to_string(),
+            usize::MAX => "always_sequential".to_string(),
+            n => format!("threshold_{}", n),
+        };
+        let config = IndexConfig::new()
+            .parent_ignore(false)
+            .parallel_threshold(threshold);
+        group.bench_function(label, |b| {
+            b.iter(|| config.index(black_box(&root)).unwrap())
+        });
+    }
+    group.finish();
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+/// The fixture tree, where files are spread evenly, and a lopsided one where
+/// a few directories hold almost all files next to many nearly empty ones.
+fn bench_size_strategy(c: &mut Criterion) {
+    let dirs = env_or("FS_INDEX_BENCH_DIRS", 500);
+    let files_per_dir = env_or("FS_INDEX_BENCH_FILES", 20);
+    let even = create_fixture("even", dirs, files_per_dir);
+    let lopsided = create_fixture("lopsided", dirs, 1);
+    for i in 0..4 {
+        let big = lopsided.join(format!("big_{}", i));
+        fs::create_dir(&big).unwrap();
+        for j in 0..dirs * files_per_dir / 4 {
+            fs::write(big.join(format!("file_{}.txt", j)), b"x").unwrap();
+        }
+    }
+
+    for (shape, root) in [("even", &even), ("lopsided", &lopsided)] {
+        let mut group = c.benchmark_group(format!("size_strategy_{}", shape));
+        for (label, strategy) in [
+            ("recursive", SizeStrategy::Recursive),
+            ("flat", SizeStrategy::Flat),
+        ] {
+            let config = IndexConfig::new()
+                .parent_ignore(false)
+                .size_strategy(strategy);
+            group.bench_function(label, |b| b.iter(|| config.index(black_box(root)).unwrap()));
+        }
+        group.finish();
+    }
+
+    fs::remove_dir_all(&even).unwrap();
+    fs::remove_dir_all(&lopsided).unwrap();
+}
+
+/// Stats every file of the fixture one after the other, so the difference is
+/// the syscall rather than the walk.
+fn bench_file_size(c: &mut Criterion) {
+    let dirs = env_or("FS_INDEX_BENCH_DIRS", 500);
+    let files_per_dir = env_or("FS_INDEX_BENCH_FILES", 20);
+    let root = create_fixture("file-size", dirs, files_per_dir);
+    let files: Vec<PathBuf> = WalkDir::new(&root)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .map(|entry| entry.into_path())
+        .collect();
+
+    let mut group = c.benchmark_group(format!("file_size_{}", files.len()));
+    group.bench_function("metadata_len", |b| {
+        b.iter(|| {
+            files
+                .iter()
+                .map(|path| fs::metadata(black_box(path)).unwrap().len())
+                .sum::<u64>()
+        })
+    });
+    group.bench_function("file_size", |b| {
+        b.iter(|| {
+            files
+                .iter()
+                .map(|path| file_size(black_box(path)).unwrap())
+                .sum::<u64>()
+        })
+    });
+    group.finish();
+
+    fs::remove_dir_all(&root).unwrap();
+}
+
+criterion_group!(
+    benches,
+    bench_index,
+    bench_parallel_threshold,
+    bench_size_strategy,
+    bench_file_size
+);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+//! Totals over NDJSON exports (see [`crate::flat::write_ndjson`]), read one
+//! line at a time so that exports of any size, e.g. one per host, can be
+//! combined into one report without building a tree. Only the totals, one
+//! entry per extension and the largest files are kept in memory.
+
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::io::{self, BufRead};
+use serde::Deserialize;
+use crate::NodeType;
+
+/// What the aggregates need of an NDJSON line. A summary line has no path or
+/// size, hence the defaults.
+#[derive(Deserialize)]
+struct Row {
+    r#type: String,
+    #[serde(default)]
+    path: String,
+    #[serde(default)]
+    size: u64,
+}
+
+/// Size and number of the files with one extension.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ExtensionTotal {
+    pub size: u64,
+    pub files: u64,
+}
+
+/// A file among the largest, with the name of the export it came from.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct LargestFile {
+    pub size: u64,
+    pub source: String,
+    pub path: String,
+}
+
+/// The totals of the files of every export added so far. Directories,
+/// symlinks and special files are skipped, since a directory's size is
+/// already the sum of its files. Gitignored and collapsed directories, whose
+/// files were not exported, add to `total_size` only.
+#[derive(Debug, Clone)]
+pub struct Aggregate {
+    pub total_size: u64,
+    pub file_count: u64,
+    /// Totals by lowercased extension, `""` for files without one.
+    pub by_extension: HashMap<String, ExtensionTotal>,
+    top: usize,
+    /// The `top` largest files seen, smallest first to be dropped first.
+    largest: BinaryHeap<Reverse<LargestFile>>,
+}
+
+impl Aggregate {
+    /// An empty aggregate that keeps the `top` largest files.
+    pub fn new(top: usize) -> Self {
+        Aggregate {
+            total_size: 0,
+            file_count: 0,
+            by_extension: HashMap::new(),
+            top,
+            largest: BinaryHeap::new(),
+        }
+    }
+
+    /// Adds every file line of the NDJSON export read from `reader`, naming
+    /// it `source` in [`Aggregate::largest`]. Fails on the first line that
+    /// isn't a node or summary object, saying which line it was.
+    pub fn add_ndjson<R: BufRead>(&mut self, source: &str, reader: R) -> io::Result<()> {
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let row: Row = serde_json::from_str(&line).map_err(|err| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} line {}: {}", source, number + 1, err),
+                )
+            })?;
+            if row.r#type == NodeType::File.as_str() {
+                self.add_file(source, row.path, row.size);
+            } else if row.r#type == NodeType::IgnoredDirectory.as_str()
+                || row.r#type == NodeType::CollapsedDirectory.as_str()
+            {
+                self.total_size += row.size;
+            }
+        }
+        Ok(())
+    }
+
+    fn add_file(&mut self, source: &str, path: String, size: u64) {
+        self.total_size += size;
+        self.file_count += 1;
+        let total = self.by_extension.entry(extension(&path)).or_default();
+        total.size += size;
+        total.files += 1;
+
+        if self.top == 0 {
+            return;
+        }
+        if self.largest.len() == self.top {
+            match self.largest.peek() {
+                Some(Reverse(smallest)) if smallest.size < size => {
+                    self.largest.pop();
+                }
+                _ => return,
+            }
+        }
+        self.largest.push(Reverse(LargestFile {
+            size,
+            source: source.to_string(),
+            path,
+        }));
+    }
+
+    /// The largest files, largest first.
+    pub fn largest(&self) -> Vec<LargestFile> {
+        let mut largest: Vec<_> = self
+            .largest
+            .iter()
+            .map(|Reverse(file)| file.clone())
+            .collect();
+        largest.sort_by(|a, b| b.cmp(a));
+        largest
+    }
+
+    /// The extension totals, largest size first.
+    pub fn extensions(&self) -> Vec<(&str, &ExtensionTotal)> {
+        let mut extensions: Vec<_> = self
+            .by_extension
+            .iter()
+            .map(|(ext, total)| (ext.as_str(), total))
+            .collect();
+        extensions.sort_by(|a, b| b.1.size.cmp(&a.1.size).then(a.0.cmp(b.0)));
+        extensions
+    }
+}
+
+/// The lowercased extension of the last component of `path`. Dotfiles like
+/// `.bashrc` have none.
+fn extension(path: &str) -> String {
+    let name = path.rsplit(['/', '\\']).next().unwrap_or(path);
+    match name.rfind('.') {
+        Some(dot) if dot > 0 => name[dot + 1..].to_lowercase(),
+        _ => String::new(),
+    }
+}
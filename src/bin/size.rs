@@ -1,16 +1,55 @@
 use bytesize::ByteSize;
+use fs_index::file_size;
+use log::debug;
 use rayon::prelude::*;
 use std::fs;
-use std::io;
+use std::io::{self, IsTerminal};
 use std::path::Path;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
 
-fn calculate_folder_size(path: &Path) -> io::Result<u64> {
+/// How [`calculate_folder_size`] counts.
+#[derive(Clone, Copy)]
+struct SizeOptions<'a> {
+    /// If given, every file's size is also added to it as soon as the file
+    /// is stat'ed, so another thread can poll a growing total while the walk
+    /// runs; it ends up equal to the returned total plus whatever it started
+    /// at.
+    counted: Option<&'a AtomicU64>,
+    /// Add each directory's own size (`metadata.len()`, the space its entry
+    /// list takes on most filesystems), closer to what `du` reports. Costs a
+    /// stat per directory.
+    include_dirs: bool,
+    /// Get file sizes with [`file_size`], a size-only `statx` on Linux,
+    /// instead of the entry's full metadata.
+    statx: bool,
+}
+
+/// Sums the size of everything below `path`.
+fn calculate_folder_size(path: &Path, options: SizeOptions) -> io::Result<u64> {
     let metadata = fs::metadata(path)?;
 
     if metadata.is_file() {
+        if let Some(counted) = options.counted {
+            counted.fetch_add(metadata.len(), Ordering::Relaxed);
+        }
         return Ok(metadata.len());
     }
+    // FIFOs, sockets and devices count as 0 bytes and are never opened.
+    if !metadata.is_dir() {
+        return Ok(0);
+    }
+    let own_size = if options.include_dirs {
+        metadata.len()
+    } else {
+        0
+    };
+    Ok(own_size + dir_size(path, options)?)
+}
+
+fn dir_size(path: &Path, options: SizeOptions) -> io::Result<u64> {
+    debug!("entering directory {}", path.display());
 
     fs::read_dir(path)?
         .par_bridge()
@@ -18,23 +57,95 @@ fn calculate_folder_size(path: &Path) -> io::Result<u64> {
             || 0,
             |acc, entry| {
                 let entry = entry?;
-                let size = calculate_folder_size(&entry.path())?;
+                // The listing usually carries the entry's type, so only files
+                // and symlinks need a stat.
+                let file_type = entry.file_type()?;
+                let size = if file_type.is_dir() {
+                    let own_size = if options.include_dirs {
+                        entry.metadata()?.len()
+                    } else {
+                        0
+                    };
+                    own_size + dir_size(&entry.path(), options)?
+                } else if file_type.is_file() {
+                    let len = if options.statx {
+                        file_size(&entry.path())?
+                    } else {
+                        entry.metadata()?.len()
+                    };
+                    if let Some(counted) = options.counted {
+                        counted.fetch_add(len, Ordering::Relaxed);
+                    }
+                    len
+                } else if file_type.is_symlink() {
+                    calculate_folder_size(&entry.path(), options)?
+                } else {
+                    0
+                };
                 Ok(acc + size)
             },
         )
         .try_reduce(|| 0, |a, b| Ok(a + b))
 }
 
+/// Redraws the total counted so far on stderr until `done` is set.
+fn report_counted(counted: &AtomicU64, done: &AtomicBool) {
+    while !done.load(Ordering::Relaxed) {
+        eprint!(
+            "\rCounted: {}   ",
+            ByteSize::b(counted.load(Ordering::Relaxed))
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+    eprint!("\r");
+}
+
 fn main() {
+    env_logger::init();
+
     let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        println!("Usage: {} <folder_path>", args[0]);
+    let usage = || {
+        println!(
+            "Usage: {} <folder_path> [--include-dirs] [--statx]",
+            args[0]
+        )
+    };
+    let [_, folder_path, flags @ ..] = args.as_slice() else {
+        usage();
         return;
+    };
+    let mut include_dirs = false;
+    let mut statx = false;
+    for flag in flags {
+        match flag.as_str() {
+            "--include-dirs" => include_dirs = true,
+            "--statx" => statx = true,
+            _ => {
+                usage();
+                return;
+            }
+        }
     }
 
-    let folder_path = Path::new(&args[1]);
+    let folder_path = Path::new(folder_path);
     let start = Instant::now();
-    match calculate_folder_size(folder_path) {
+    // The running total is only worth drawing for someone watching.
+    let counted = io::stderr().is_terminal().then(|| AtomicU64::new(0));
+    let done = AtomicBool::new(false);
+    let result = thread::scope(|scope| {
+        if let Some(counted) = &counted {
+            scope.spawn(|| report_counted(counted, &done));
+        }
+        let options = SizeOptions {
+            counted: counted.as_ref(),
+            include_dirs,
+            statx,
+        };
+        let result = calculate_folder_size(folder_path, options);
+        done.store(true, Ordering::Relaxed);
+        result
+    });
+    match result {
         Ok(size) => println!("Total size: {}", ByteSize::b(size)),
         Err(e) => eprintln!("Error: {}", e),
     }
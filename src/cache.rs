@@ -0,0 +1,140 @@
+//! Sidecar cache that lets separate runs reuse unchanged subtrees.
+//!
+//! A cached subtree is keyed by its directory's path and mtime. Because a
+//! directory's mtime only changes when entries are added, removed or renamed
+//! directly inside it, a hit also requires every directory below it to still
+//! have its cached mtime. Only directories are stat'ed for that check, never
+//! files, so edits that rewrite an existing file in place (changing its size
+//! but not its directory's listing) are not detected. Delete the cache file to
+//! force a full walk.
+
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::{FileNode, NodeType};
+
+/// What a cached tree was indexed from and with which options. A tree is
+/// only reused by a run whose key is the same.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CacheKey {
+    /// Canonical path of the indexed root.
+    pub root: PathBuf,
+    /// Whether names were NFC-normalized, which changes how children are
+    /// matched against the directory listing.
+    pub normalize_unicode: bool,
+    /// The `--exclude-regex` patterns, since a subtree indexed without them
+    /// may hold entries they would now leave out.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    /// The uid entries were limited to by `--owner-only`, if any.
+    #[serde(default)]
+    pub owner: Option<u32>,
+    /// The ignore file names read in each directory.
+    #[serde(default)]
+    pub ignore_files: Vec<String>,
+    /// The `--ignore-rules` lines.
+    #[serde(default)]
+    pub ignore_rules: Vec<String>,
+    /// Whether files were sized by their allocated blocks, see
+    /// [`crate::IndexConfig::physical_size`].
+    #[serde(default)]
+    pub physical_size: bool,
+    /// The `--max-depth` below which directories were collapsed, if any.
+    #[serde(default)]
+    pub max_depth: Option<usize>,
+    /// Whether symlinks were indexed as their targets rather than recorded
+    /// as links.
+    #[serde(default = "default_true")]
+    pub follow_symlinks: bool,
+    /// Whether `.gitignore` files above the root were applied.
+    #[serde(default = "default_true")]
+    pub parent_ignore: bool,
+}
+
+/// Caches written before a switch was part of the key were built with its
+/// default, which is on.
+fn default_true() -> bool {
+    true
+}
+
+/// The tree of a previous run, along with what it was indexed from.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TreeCache {
+    #[serde(flatten)]
+    pub key: CacheKey,
+    pub tree: FileNode,
+}
+
+impl TreeCache {
+    /// Reads a cache file. A missing or unreadable cache is not an error: it
+    /// only means every subtree is walked again.
+    pub fn load(path: &Path) -> Option<TreeCache> {
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return None,
+            Err(err) => {
+                warn!("ignoring cache {}: {}", path.display(), err);
+                return None;
+            }
+        };
+        match serde_json::from_reader(BufReader::new(file)) {
+            Ok(cache) => Some(cache),
+            Err(err) => {
+                warn!("ignoring cache {}: {}", path.display(), err);
+                None
+            }
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+
+    /// The cached tree, if it was indexed with the same `key`.
+    pub fn tree_for(&self, key: &CacheKey) -> Option<&FileNode> {
+        if self.key != *key {
+            debug!(
+                "cache was built for {} with other options, not using it",
+                self.key.root.display()
+            );
+            return None;
+        }
+        Some(&self.tree)
+    }
+}
+
+/// Whether `cached` can stand in for the directory at `path` without walking
+/// it. Subtrees containing ignored or collapsed directories never qualify,
+/// since their contents were not recorded and cannot be checked.
+pub(crate) fn is_fresh(cached: &FileNode, path: &Path, metadata: &Metadata) -> bool {
+    matches!(cached.node_type, NodeType::Directory)
+        && cached.mtime.is_some()
+        && metadata.modified().ok() == cached.mtime
+        && children_fresh(cached, path)
+}
+
+fn children_fresh(cached: &FileNode, path: &Path) -> bool {
+    cached.children.iter().all(|child| match child.node_type {
+        NodeType::File | NodeType::Special => true,
+        NodeType::Directory => {
+            let child_path = path.join(&child.name);
+            match fs::metadata(&child_path) {
+                Ok(metadata) => {
+                    metadata.is_dir()
+                        && metadata.modified().ok() == child.mtime
+                        && children_fresh(child, &child_path)
+                }
+                Err(_) => false,
+            }
+        }
+        // A broken symlink's target may have appeared without touching the
+        // directory holding the link.
+        NodeType::IgnoredDirectory | NodeType::CollapsedDirectory | NodeType::Symlink { .. } => {
+            false
+        }
+    })
+}
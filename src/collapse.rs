@@ -0,0 +1,175 @@
+//! Shrinking a tree for output by collapsing subtrees into
+//! [`NodeType::CollapsedDirectory`] nodes, either until it fits a budget or
+//! wherever they are small.
+
+use crate::{FileNode, NodeType};
+
+/// What [`FileNode::collapse_to_budget`] did.
+#[derive(Debug, Default)]
+pub struct CollapseSummary {
+    /// Number of collapsed directories left in the tree.
+    pub collapsed: usize,
+    /// Number of descendant nodes dropped from the tree.
+    pub dropped: usize,
+    /// False if the output is still over budget with every directory
+    /// collapsed.
+    pub fits: bool,
+}
+
+impl FileNode {
+    /// Number of nodes in the tree, including this one.
+    pub fn node_count(&self) -> usize {
+        1 + self
+            .children
+            .iter()
+            .map(FileNode::node_count)
+            .sum::<usize>()
+    }
+
+    /// Collapses the least significant directories until `measure(self)`,
+    /// typically the serialized size, is at most `budget`.
+    ///
+    /// Directories are collapsed smallest first (deepest first among equal
+    /// sizes), so the largest branches keep their full detail. Sizes and
+    /// counts are unaffected.
+    pub fn collapse_to_budget<F: Fn(&FileNode) -> u64>(
+        &mut self,
+        budget: u64,
+        measure: F,
+    ) -> CollapseSummary {
+        if measure(self) <= budget {
+            return CollapseSummary {
+                fits: true,
+                ..Default::default()
+            };
+        }
+
+        let mut candidates = Vec::new();
+        collect_candidates(self, &mut Vec::new(), &mut candidates);
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then(b.1.len().cmp(&a.1.len())));
+        let candidates: Vec<Vec<usize>> = candidates.into_iter().map(|(_, path)| path).collect();
+
+        // Collapsing more directories only ever shrinks the output, so binary
+        // search for the smallest prefix of candidates that fits.
+        let collapsed_with = |count: usize| {
+            let mut tree = self.clone();
+            let summary = collapse_all(&mut tree, &candidates[..count]);
+            (tree, summary)
+        };
+        let (mut low, mut high) = (1, candidates.len());
+        let (tree, mut summary) = collapsed_with(high);
+        if measure(&tree) > budget {
+            *self = tree;
+            return summary;
+        }
+        let mut best = tree;
+        while low < high {
+            let mid = (low + high) / 2;
+            let (tree, mid_summary) = collapsed_with(mid);
+            if measure(&tree) <= budget {
+                high = mid;
+                best = tree;
+                summary = mid_summary;
+            } else {
+                low = mid + 1;
+            }
+        }
+        *self = best;
+        summary.fits = true;
+        summary
+    }
+
+    /// Replaces, in every directory, the children smaller than `threshold`
+    /// with one [`NodeType::CollapsedDirectory`] named `(N small items)`
+    /// holding their total size and counts, after rolling up inside the
+    /// children that stay. A directory with only one small child keeps it,
+    /// since a summary wouldn't be any shorter. Sizes and counts of every
+    /// remaining node are unaffected, so branches of at least `threshold`
+    /// keep their full detail at any depth. Returns the number of children
+    /// rolled up.
+    pub fn rollup_below(&mut self, threshold: u64) -> usize {
+        let mut rolled_up = 0;
+        for child in &mut self.children {
+            if child.size >= threshold {
+                rolled_up += child.rollup_below(threshold);
+            }
+        }
+        let small = self
+            .children
+            .iter()
+            .filter(|child| child.size < threshold)
+            .count();
+        if small < 2 {
+            return rolled_up;
+        }
+        let mut summary = FileNode::new(
+            format!("({} small items)", small),
+            0,
+            NodeType::CollapsedDirectory,
+        );
+        self.children.retain(|child| {
+            if child.size >= threshold {
+                return true;
+            }
+            summary.size += child.size;
+            summary.indexed_size += child.indexed_size;
+            summary.ignored_size += child.ignored_size;
+            summary.file_count += child.file_count;
+            summary.dir_count += child.dir_count;
+            if matches!(
+                child.node_type,
+                NodeType::Directory | NodeType::IgnoredDirectory | NodeType::CollapsedDirectory
+            ) {
+                summary.dir_count += 1;
+            }
+            false
+        });
+        self.children.push(summary);
+        rolled_up + small
+    }
+}
+
+/// Collects every directory that still has children, with its size and the
+/// child indices leading to it.
+fn collect_candidates(
+    node: &FileNode,
+    path: &mut Vec<usize>,
+    candidates: &mut Vec<(u64, Vec<usize>)>,
+) {
+    if !matches!(node.node_type, NodeType::Directory) || node.children.is_empty() {
+        return;
+    }
+    candidates.push((node.size, path.clone()));
+    for (index, child) in node.children.iter().enumerate() {
+        path.push(index);
+        collect_candidates(child, path, candidates);
+        path.pop();
+    }
+}
+
+fn collapsed_count(node: &FileNode) -> usize {
+    let own = matches!(node.node_type, NodeType::CollapsedDirectory) as usize;
+    own + node.children.iter().map(collapsed_count).sum::<usize>()
+}
+
+fn collapse_all(root: &mut FileNode, paths: &[Vec<usize>]) -> CollapseSummary {
+    let mut summary = CollapseSummary::default();
+    let collapsed_before = collapsed_count(root);
+    for path in paths {
+        // A candidate below an already collapsed directory no longer exists.
+        let Some(node) = path
+            .iter()
+            .try_fold(&mut *root, |node, &index| node.children.get_mut(index))
+        else {
+            continue;
+        };
+        if !matches!(node.node_type, NodeType::Directory) {
+            continue;
+        }
+        summary.dropped += node.node_count() - 1;
+        node.children.clear();
+        node.node_type = NodeType::CollapsedDirectory;
+    }
+    summary.collapsed = collapsed_count(root) - collapsed_before;
+    summary
+}
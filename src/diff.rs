@@ -0,0 +1,153 @@
+//! Structural comparison of two trees, e.g. a live index against an expected
+//! tree saved earlier. Children are matched by name, so the order they were
+//! listed in doesn't matter.
+//!
+//! [`size_deltas`] answers a different question about the same pair of trees:
+//! not what differs, but which directories account for a change in the total.
+
+use std::collections::BTreeMap;
+use crate::{join_relative, FileNode, NodeType};
+
+/// A node present in both trees that differs between them.
+#[derive(Debug)]
+pub struct Changed {
+    /// Path relative to the root, see [`crate::PathStyle::render`].
+    pub path: String,
+    /// What differs, e.g. `size 10 -> 12`.
+    pub detail: String,
+}
+
+/// Differences between an expected tree and the actual one. Paths are
+/// relative to the root and sorted.
+#[derive(Debug, Default)]
+pub struct TreeDiff {
+    /// Nodes only in the actual tree.
+    pub added: Vec<String>,
+    /// Nodes only in the expected tree.
+    pub removed: Vec<String>,
+    pub changed: Vec<Changed>,
+}
+
+impl TreeDiff {
+    pub fn is_clean(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+}
+
+/// Compares node types, sizes, file counts and, where both trees have them,
+/// file hashes. Root names, mtimes, atimes and rendered paths are not
+/// compared, so an expected tree saved from another checkout still matches.
+pub fn diff_trees(expected: &FileNode, actual: &FileNode) -> TreeDiff {
+    let mut diff = TreeDiff::default();
+    compare(expected, actual, "", &mut diff);
+    diff.added.sort();
+    diff.removed.sort();
+    diff.changed.sort_by(|a, b| a.path.cmp(&b.path));
+    diff
+}
+
+fn compare(expected: &FileNode, actual: &FileNode, path: &str, diff: &mut TreeDiff) {
+    let mut change = |detail: String| {
+        diff.changed.push(Changed {
+            path: path.to_string(),
+            detail,
+        })
+    };
+    if expected.node_type.as_str() != actual.node_type.as_str() {
+        change(format!(
+            "type {} -> {}",
+            expected.node_type.as_str(),
+            actual.node_type.as_str()
+        ));
+        // Children of different kinds of node aren't comparable.
+        return;
+    }
+    if expected.size != actual.size {
+        change(format!("size {} -> {}", expected.size, actual.size));
+    }
+    if expected.file_count != actual.file_count {
+        change(format!(
+            "file count {} -> {}",
+            expected.file_count, actual.file_count
+        ));
+    }
+    if let (Some(expected_hash), Some(actual_hash)) = (&expected.hash, &actual.hash) {
+        if expected_hash != actual_hash {
+            change(format!("hash {} -> {}", expected_hash, actual_hash));
+        }
+    }
+    if !matches!(actual.node_type, NodeType::Directory) {
+        return;
+    }
+
+    let mut actual_children: BTreeMap<&str, &FileNode> = actual
+        .children
+        .iter()
+        .map(|child| (child.name.as_str(), child))
+        .collect();
+    for child in &expected.children {
+        let child_path = join_relative(path, &child.name);
+        match actual_children.remove(child.name.as_str()) {
+            Some(actual_child) => compare(child, actual_child, &child_path, diff),
+            None => diff.removed.push(child_path),
+        }
+    }
+    diff.added.extend(
+        actual_children
+            .into_keys()
+            .map(|name| join_relative(path, name)),
+    );
+}
+
+/// How the size of one directory changed between two trees.
+#[derive(Debug, Clone)]
+pub struct SizeDelta {
+    /// Path relative to the root, see [`crate::PathStyle::render`].
+    pub path: String,
+    /// Size in the old tree, 0 if the directory wasn't there.
+    pub old: u64,
+    /// Size in the new tree, 0 if the directory is gone.
+    pub new: u64,
+}
+
+impl SizeDelta {
+    /// The growth in bytes, negative if the directory shrank.
+    pub fn change(&self) -> i128 {
+        self.new as i128 - self.old as i128
+    }
+}
+
+/// The directories below the roots of `old` and `new` whose size differs,
+/// matched by path, largest absolute change first (ties by path).
+/// Directories in only one of the trees count as size 0 in the other.
+/// Ignored and collapsed directories are included with their totals, while
+/// what is inside them can't be told apart. Each directory's change includes
+/// its subdirectories', so the parents of one growing directory rank right
+/// along with it; the first directory whose children changed less is where
+/// the growth is.
+pub fn size_deltas(old: &FileNode, new: &FileNode) -> Vec<SizeDelta> {
+    fn sizes(root: &FileNode) -> impl Iterator<Item = (String, u64)> + '_ {
+        root.all().filter_map(|(path, node)| {
+            let is_dir = matches!(
+                node.node_type,
+                NodeType::Directory | NodeType::IgnoredDirectory | NodeType::CollapsedDirectory
+            );
+            (is_dir && !path.is_empty()).then_some((path, node.size))
+        })
+    }
+
+    let mut both: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for (path, size) in sizes(old) {
+        both.entry(path).or_default().0 = size;
+    }
+    for (path, size) in sizes(new) {
+        both.entry(path).or_default().1 = size;
+    }
+    let mut deltas: Vec<SizeDelta> = both
+        .into_iter()
+        .filter(|(_, (old, new))| old != new)
+        .map(|(path, (old, new))| SizeDelta { path, old, new })
+        .collect();
+    deltas.sort_by_key(|delta| std::cmp::Reverse(delta.change().unsigned_abs()));
+    deltas
+}
@@ -0,0 +1,408 @@
+//! Indexing from an open directory file descriptor instead of a path.
+//!
+//! Every entry is reached with `openat`, `statat` and `readlinkat` relative
+//! to its parent's descriptor, so no path is ever resolved again after the
+//! root was opened. Renaming or swapping a directory above or inside the tree
+//! while it is walked can't redirect the walk elsewhere, and the walk works
+//! where paths can't be resolved at all, e.g. in a namespace that only
+//! received the descriptor.
+//!
+//! That rules out what needs paths: symlinks are never followed and become
+//! [`NodeType::Symlink`] nodes whose `resolved` is always `None`, ignore files
+//! above the root are not read, and there is no cache or progress. Birth
+//! times are not recorded. The walk is sequential.
+
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::fd::{AsFd, BorrowedFd, OwnedFd};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::{debug, warn};
+use rustix::fs::{openat, readlinkat, statat, AtFlags, Dir, FileType, Mode, OFlags, Stat};
+use crate::{
+    entry_error, inline_ignore, is_ignored, join_relative, normalize_name, skip_entry, vanished,
+    with_retries, FileNode, IgnoredSizePolicy, IndexConfig, Name, NamePool, NodeType,
+};
+
+impl IndexConfig {
+    /// Indexes the directory open as `dir`, naming the root `name`. Only
+    /// `*at` calls relative to `dir` and descriptors opened from it are
+    /// used; see the [module documentation](self) for what that leaves out.
+    /// The descriptor is only borrowed and stays open.
+    pub fn index_dir_fd(&self, dir: BorrowedFd<'_>, name: &str) -> io::Result<FileNode> {
+        let stat = rustix::fs::fstat(dir)?;
+        if FileType::from_raw_mode(stat.st_mode) != FileType::Directory {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{} is not a directory", name),
+            ));
+        }
+        let inline = inline_ignore(&rule_path(""), &self.ignore_rules);
+        let walk = FdWalk {
+            config: self,
+            names: self.intern_names.then(NamePool::new),
+        };
+        let name = walk.name(name.to_string());
+        let mut root = index_fd_dir(&walk, dir, name, "", &[&inline], 0)?;
+        root.mtime = Some(mtime(&stat));
+        root.atime = Some(atime(&stat));
+        if self.max_depth == Some(0) {
+            root.children.clear();
+            root.node_type = NodeType::CollapsedDirectory;
+        }
+        Ok(root)
+    }
+}
+
+/// What stays the same throughout one descriptor walk.
+struct FdWalk<'a> {
+    config: &'a IndexConfig,
+    /// Where names come from with [`IndexConfig::intern_names`].
+    names: Option<NamePool>,
+}
+
+impl FdWalk<'_> {
+    fn name(&self, name: String) -> Name {
+        match &self.names {
+            Some(pool) => pool.intern(name),
+            None => name.into(),
+        }
+    }
+}
+
+/// Where ignore rules of the directory at `relative` match from. Paths are
+/// rooted at `/` so they stay apart from real paths in debug output.
+fn rule_path(relative: &str) -> PathBuf {
+    Path::new("/").join(relative)
+}
+
+/// The size recorded for the file `stat` describes, see
+/// [`IndexConfig::physical_size`].
+fn file_size(config: &IndexConfig, stat: &Stat) -> u64 {
+    if config.physical_size {
+        stat.st_blocks as u64 * 512
+    } else {
+        stat.st_size as u64
+    }
+}
+
+fn mtime(stat: &Stat) -> SystemTime {
+    time(stat.st_mtime, stat.st_mtime_nsec as u32)
+}
+
+fn atime(stat: &Stat) -> SystemTime {
+    time(stat.st_atime, stat.st_atime_nsec as u32)
+}
+
+fn time(secs: i64, nanos: u32) -> SystemTime {
+    let since = Duration::new(secs.unsigned_abs(), nanos);
+    if secs >= 0 {
+        UNIX_EPOCH + since
+    } else {
+        UNIX_EPOCH - since
+    }
+}
+
+/// The entries of `dir`, at `relative`, other than `.` and `..`, with the
+/// type the listing reports. Retried like [`IndexConfig::io_retries`] says,
+/// as are [`open_dir`] and [`stat_entry`].
+fn list(
+    config: &IndexConfig,
+    dir: BorrowedFd<'_>,
+    relative: &str,
+) -> io::Result<Vec<(Vec<u8>, FileType)>> {
+    with_retries(config.io_retries, &rule_path(relative), || {
+        let mut entries = Vec::new();
+        for entry in Dir::read_from(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_bytes();
+            if name != b"." && name != b".." {
+                entries.push((name.to_vec(), entry.file_type()));
+            }
+        }
+        Ok(entries)
+    })
+}
+
+/// Opens the directory `name` in `dir`, whose path is `relative`.
+fn open_dir(
+    config: &IndexConfig,
+    dir: BorrowedFd<'_>,
+    name: &CStr,
+    relative: &str,
+) -> io::Result<OwnedFd> {
+    with_retries(config.io_retries, &rule_path(relative), || {
+        Ok(openat(
+            dir,
+            name,
+            OFlags::RDONLY | OFlags::DIRECTORY | OFlags::NOFOLLOW | OFlags::CLOEXEC,
+            Mode::empty(),
+        )?)
+    })
+}
+
+/// Stats the entry `name` in `dir`, whose path is `relative`, without
+/// following it.
+fn stat_entry(
+    config: &IndexConfig,
+    dir: BorrowedFd<'_>,
+    name: &CStr,
+    relative: &str,
+) -> io::Result<Stat> {
+    with_retries(config.io_retries, &rule_path(relative), || {
+        Ok(statat(dir, name, AtFlags::SYMLINK_NOFOLLOW)?)
+    })
+}
+
+/// Reads the ignore files of `dir` found among `entries`, layered like
+/// [`crate::read_ignore_files`].
+fn read_ignore_files_at(
+    config: &IndexConfig,
+    dir: BorrowedFd<'_>,
+    relative: &str,
+    entries: &[(Vec<u8>, FileType)],
+) -> io::Result<Option<Gitignore>> {
+    let mut builder = GitignoreBuilder::new(rule_path(relative));
+    let mut found = false;
+    for ignore_file in &config.ignore_files {
+        if !entries
+            .iter()
+            .any(|(name, _)| name == ignore_file.as_bytes())
+        {
+            continue;
+        }
+        let Ok(c_name) = CString::new(ignore_file.as_bytes()) else {
+            continue;
+        };
+        let fd = openat(
+            dir,
+            c_name.as_c_str(),
+            OFlags::RDONLY | OFlags::NOFOLLOW | OFlags::CLOEXEC,
+            Mode::empty(),
+        )?;
+        let mut contents = String::new();
+        File::from(fd).read_to_string(&mut contents)?;
+        debug!("loading {}", join_relative(relative, ignore_file));
+        let from = rule_path(&join_relative(relative, ignore_file));
+        for line in contents.lines() {
+            if let Err(err) = builder.add_line(Some(from.clone()), line) {
+                warn!("error in {}: {}", from.display(), err);
+            }
+        }
+        found = true;
+    }
+    Ok(found.then(|| builder.build().unwrap()))
+}
+
+fn index_fd_dir(
+    walk: &FdWalk,
+    dir: BorrowedFd<'_>,
+    name: Name,
+    relative: &str,
+    gitignores: &[&Gitignore],
+    depth: usize,
+) -> io::Result<FileNode> {
+    let config = walk.config;
+    debug!("entering directory {}", rule_path(relative).display());
+    let entries = list(config, dir, relative)?;
+    let local = read_ignore_files_at(config, dir, relative, &entries)?;
+    let mut stack = gitignores.to_vec();
+    stack.extend(local.as_ref());
+
+    let mut node = FileNode::with_name(name, 0, NodeType::Directory);
+    for (raw_name, listed_type) in &entries {
+        let mut child_name = OsStr::from_bytes(raw_name).to_string_lossy().into_owned();
+        let child_relative = join_relative(relative, &child_name);
+        if config.exclude.is_match(&child_relative) {
+            debug!("{} matched --exclude-regex", child_relative);
+            continue;
+        }
+        // The listing tells files apart, so with names only they cost no
+        // stat unless their owner is needed.
+        if config.names_only && *listed_type == FileType::RegularFile && config.owner.is_none() {
+            if is_ignored(&stack, &rule_path(&child_relative), false) {
+                debug!("{} matched gitignore, skipping it", child_relative);
+                continue;
+            }
+            if config.normalize_unicode {
+                child_name = normalize_name(&child_name);
+            }
+            node.add_child(FileNode::with_name(
+                walk.name(child_name),
+                0,
+                NodeType::File,
+            ));
+            continue;
+        }
+        let c_name = CString::new(raw_name.clone()).expect("names contain no NUL");
+        match index_fd_entry(
+            walk,
+            dir,
+            &c_name,
+            child_name,
+            &child_relative,
+            &stack,
+            depth + 1,
+        ) {
+            Ok(Some(child)) => node.add_child(child),
+            Ok(None) => {}
+            Err(err) if config.strict => return Err(entry_error(&child_relative, err)),
+            Err(err) => skip_entry(&child_relative, &err),
+        }
+    }
+    Ok(node)
+}
+
+fn index_fd_entry(
+    walk: &FdWalk,
+    parent: BorrowedFd<'_>,
+    c_name: &CStr,
+    mut name: String,
+    relative: &str,
+    gitignores: &[&Gitignore],
+    depth: usize,
+) -> io::Result<Option<FileNode>> {
+    let config = walk.config;
+    let stat = stat_entry(config, parent, c_name, relative)?;
+    if let Some(uid) = config.owner {
+        if stat.st_uid != uid {
+            debug!("{} is not owned by uid {}, skipping it", relative, uid);
+            return Ok(None);
+        }
+    }
+    if config.normalize_unicode {
+        name = normalize_name(&name);
+    }
+    let name = walk.name(name);
+    let file_type = FileType::from_raw_mode(stat.st_mode);
+    let is_dir = file_type == FileType::Directory;
+    if is_ignored(gitignores, &rule_path(relative), is_dir) {
+        if !is_dir {
+            debug!("{} matched gitignore, skipping it", relative);
+            return Ok(None);
+        }
+        debug!(
+            "{} matched gitignore, summing size without storing structure",
+            relative
+        );
+        let fd = open_dir(config, parent, c_name, relative)?;
+        let (size, file_count, dir_count) = sum_fd(config, fd.as_fd(), relative, &[])?;
+        let mut ignored = FileNode::with_name(name, size, NodeType::IgnoredDirectory);
+        ignored.file_count = file_count;
+        ignored.dir_count = dir_count;
+        ignored.mtime = Some(mtime(&stat));
+        ignored.atime = Some(atime(&stat));
+        return Ok(Some(ignored));
+    }
+    let node = match file_type {
+        FileType::RegularFile if config.names_only => FileNode::with_name(name, 0, NodeType::File),
+        FileType::RegularFile => {
+            let mut node = FileNode::with_name(name, file_size(config, &stat), NodeType::File);
+            node.mtime = Some(mtime(&stat));
+            node.atime = Some(atime(&stat));
+            node
+        }
+        FileType::Symlink => {
+            let target = readlinkat(parent, c_name, Vec::new())?;
+            let target = PathBuf::from(OsStr::from_bytes(target.as_bytes()));
+            debug!(
+                "{} is a symlink to {}, not following it",
+                relative,
+                target.display()
+            );
+            FileNode::with_name(
+                name,
+                0,
+                NodeType::Symlink {
+                    target,
+                    resolved: None,
+                },
+            )
+        }
+        FileType::Directory => {
+            let fd = open_dir(config, parent, c_name, relative)?;
+            let mut node = index_fd_dir(walk, fd.as_fd(), name, relative, gitignores, depth)?;
+            node.mtime = Some(mtime(&stat));
+            node.atime = Some(atime(&stat));
+            if config.max_depth == Some(depth) {
+                node.children.clear();
+                node.node_type = NodeType::CollapsedDirectory;
+            }
+            node
+        }
+        _ => {
+            debug!("{} is a special file, not opening it", relative);
+            FileNode::with_name(name, 0, NodeType::Special)
+        }
+    };
+    Ok(Some(node))
+}
+
+/// Like [`crate::calculate_ignored_size`] for the ignored directory open as
+/// `dir`: its total size, file count and directory count.
+fn sum_fd(
+    config: &IndexConfig,
+    dir: BorrowedFd<'_>,
+    relative: &str,
+    nested: &[&Gitignore],
+) -> io::Result<(u64, u64, u64)> {
+    let entries = list(config, dir, relative)?;
+    let local = if config.ignored_size == IgnoredSizePolicy::ApplyNested {
+        read_ignore_files_at(config, dir, relative, &entries)?
+    } else {
+        None
+    };
+    let mut stack = nested.to_vec();
+    stack.extend(local.as_ref());
+
+    let (mut total_size, mut file_count, mut dir_count) = (0, 0, 0);
+    for (raw_name, listed_type) in entries {
+        let child_relative =
+            join_relative(relative, &OsStr::from_bytes(&raw_name).to_string_lossy());
+        let c_name = CString::new(raw_name).expect("names contain no NUL");
+        let is_dir = listed_type == FileType::Directory;
+        if is_ignored(&stack, &rule_path(&child_relative), is_dir) {
+            debug!(
+                "{} matched a nested gitignore, not counting it",
+                child_relative
+            );
+            continue;
+        }
+        if is_dir {
+            let summed = open_dir(config, dir, &c_name, &child_relative)
+                .and_then(|fd| sum_fd(config, fd.as_fd(), &child_relative, &stack));
+            match summed {
+                Ok((size, files, dirs)) => {
+                    total_size += size;
+                    file_count += files;
+                    dir_count += dirs + 1;
+                }
+                Err(err) if config.strict => return Err(entry_error(&child_relative, err)),
+                Err(err) if vanished(&err) => skip_entry(&child_relative, &err),
+                Err(err) => return Err(err),
+            }
+        } else if listed_type == FileType::RegularFile && config.names_only {
+            file_count += 1;
+        } else if listed_type == FileType::RegularFile || listed_type == FileType::Unknown {
+            let stat = match stat_entry(config, dir, &c_name, &child_relative) {
+                Ok(stat) => stat,
+                Err(err) if config.strict => return Err(entry_error(&child_relative, err)),
+                Err(err) if vanished(&err) => {
+                    skip_entry(&child_relative, &err);
+                    continue;
+                }
+                Err(err) => return Err(err),
+            };
+            if FileType::from_raw_mode(stat.st_mode) == FileType::RegularFile {
+                if !config.names_only {
+                    total_size += file_size(config, &stat);
+                }
+                file_count += 1;
+            }
+        }
+    }
+    Ok((total_size, file_count, dir_count))
+}
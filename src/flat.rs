@@ -0,0 +1,291 @@
+//! Flattened views of a tree, shared by the tabular exporters so paths are
+//! built in one place.
+
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::Serialize;
+use crate::{join_relative, slash_path, FileNode, NodeType, PathStyle};
+
+/// A node together with its rendered path and its place in the tree, so
+/// consumers can rebuild the hierarchy without parsing paths.
+#[derive(Debug)]
+pub struct FlatNode<'a> {
+    pub path: String,
+    pub node: &'a FileNode,
+    /// The node's position in pre-order, 0 for the root.
+    pub id: u64,
+    /// The `id` of the directory holding the node, `None` for the root.
+    pub parent_id: Option<u64>,
+    /// Levels below the root, 0 for the root itself.
+    pub depth: u64,
+}
+
+/// Lists every node of the tree in pre-order, with paths rendered in
+/// `style` relative to `root_path`. Ids number the rows in that order, so a
+/// parent's id is lower than its children's and the same tree always gets
+/// the same ids.
+pub fn flatten<'a>(root: &'a FileNode, root_path: &Path, style: PathStyle) -> Vec<FlatNode<'a>> {
+    fn visit<'a>(
+        node: &'a FileNode,
+        relative: String,
+        parent_id: Option<u64>,
+        depth: u64,
+        root_path: &Path,
+        style: PathStyle,
+        rows: &mut Vec<FlatNode<'a>>,
+    ) {
+        let id = rows.len() as u64;
+        rows.push(FlatNode {
+            path: style.render(root_path, &relative),
+            node,
+            id,
+            parent_id,
+            depth,
+        });
+        for child in &node.children {
+            let child_path = join_relative(&relative, &child.name);
+            visit(
+                child,
+                child_path,
+                Some(id),
+                depth + 1,
+                root_path,
+                style,
+                rows,
+            );
+        }
+    }
+
+    let mut rows = Vec::new();
+    visit(root, String::new(), None, 0, root_path, style, &mut rows);
+    rows
+}
+
+/// Maps each row's path to its size, for consumers that want key-value
+/// lookups rather than a nested tree. Only files are included unless
+/// `all_nodes` is set. Fails if two rows share a path (e.g. with
+/// [`PathStyle::Name`]), since a JSON object can't hold both.
+pub fn size_map<'a>(rows: &'a [FlatNode], all_nodes: bool) -> io::Result<BTreeMap<&'a str, u64>> {
+    let mut map = BTreeMap::new();
+    for row in rows {
+        if !all_nodes && !matches!(row.node.node_type, NodeType::File) {
+            continue;
+        }
+        if map.insert(row.path.as_str(), row.node.size).is_some() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("duplicate path in flat map: {}", row.path),
+            ));
+        }
+    }
+    Ok(map)
+}
+
+/// Milliseconds since the Unix epoch, as the flat formats store times.
+pub(crate) fn millis(time: Option<SystemTime>) -> Option<i64> {
+    time.and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map(|since_epoch| since_epoch.as_millis() as i64)
+}
+
+/// The totals the human-readable output prints, which the CSV and NDJSON
+/// writers can put before the rows.
+#[derive(Debug, Clone, Serialize)]
+pub struct Summary {
+    /// The indexed root as given, not rendered.
+    pub root: String,
+    pub total_size: u64,
+    pub file_count: u64,
+}
+
+impl Summary {
+    pub fn of(root: &FileNode, root_path: &Path) -> Self {
+        Summary {
+            root: slash_path(root_path),
+            total_size: root.size,
+            file_count: root.file_count,
+        }
+    }
+}
+
+/// Quotes a CSV field if it contains a comma, quote or line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Adds the row being written to a write error, to tell how far a failed
+/// export got. With a buffered writer the bytes that failed may belong to
+/// earlier rows, so it is where writing stopped rather than the culprit.
+fn at_row(err: io::Error, row: &FlatNode) -> io::Error {
+    io::Error::new(err.kind(), format!("at {}: {}", row.path, err))
+}
+
+/// Writes the flattened nodes as CSV with a
+/// `path,size,type,mtime,btime,id,parent_id,depth` header, times in
+/// milliseconds since the Unix epoch and empty when unknown, as is the root's
+/// `parent_id`.
+/// A `summary` is written as a leading `#` comment line, which most CSV
+/// readers can be told to skip.
+pub fn write_csv<W: Write>(
+    rows: &[FlatNode],
+    summary: Option<&Summary>,
+    mut writer: W,
+) -> io::Result<()> {
+    if let Some(summary) = summary {
+        writeln!(
+            writer,
+            "# root={},total_size={},file_count={}",
+            summary.root, summary.total_size, summary.file_count
+        )?;
+    }
+    writeln!(writer, "path,size,type,mtime,btime,id,parent_id,depth")?;
+    let time = |time| {
+        millis(time)
+            .map(|millis| millis.to_string())
+            .unwrap_or_default()
+    };
+    for row in rows {
+        writeln!(
+            writer,
+            "{},{},{},{},{},{},{},{}",
+            csv_field(&row.path),
+            row.node.size,
+            row.node.node_type.as_str(),
+            time(row.node.mtime),
+            time(row.node.btime),
+            row.id,
+            row.parent_id.map(|id| id.to_string()).unwrap_or_default(),
+            row.depth
+        )
+        .map_err(|err| at_row(err, row))?;
+    }
+    writer.flush()
+}
+
+#[derive(Serialize)]
+struct NdjsonSummary<'a> {
+    r#type: &'static str,
+    #[serde(flatten)]
+    summary: &'a Summary,
+}
+
+#[derive(Serialize)]
+struct NdjsonRow<'a> {
+    path: &'a str,
+    size: u64,
+    r#type: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    mtime: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    btime: Option<i64>,
+    id: u64,
+    parent_id: Option<u64>,
+    depth: u64,
+}
+
+/// Writes the flattened nodes as newline-delimited JSON, one
+/// `{"path":..,"size":..,"type":..,"mtime":..,"btime":..,"id":..,"parent_id":..,"depth":..}`
+/// object per line with times in milliseconds since the Unix epoch and a
+/// `null` `parent_id` for the root. A `summary` becomes the
+/// first line, `{"type":"summary","root":..,"total_size":..,"file_count":..}`,
+/// told apart from nodes by its `type`.
+pub fn write_ndjson<W: Write>(
+    rows: &[FlatNode],
+    summary: Option<&Summary>,
+    mut writer: W,
+) -> io::Result<()> {
+    if let Some(summary) = summary {
+        serde_json::to_writer(
+            &mut writer,
+            &NdjsonSummary {
+                r#type: "summary",
+                summary,
+            },
+        )?;
+        writeln!(writer)?;
+    }
+    for row in rows {
+        serde_json::to_writer(
+            &mut writer,
+            &NdjsonRow {
+                path: &row.path,
+                size: row.node.size,
+                r#type: row.node.node_type.as_str(),
+                mtime: millis(row.node.mtime),
+                btime: millis(row.node.btime),
+                id: row.id,
+                parent_id: row.parent_id,
+                depth: row.depth,
+            },
+        )
+        .map_err(io::Error::from)
+        .and_then(|()| writeln!(writer))
+        .map_err(|err| at_row(err, row))?;
+    }
+    writer.flush()
+}
+
+/// Writes the flattened nodes as a Parquet file with `path`, `size`, `type`,
+/// `mtime` and `btime` (both milliseconds since the Unix epoch), `id`,
+/// `parent_id` (null for the root) and `depth` columns.
+#[cfg(feature = "parquet")]
+pub fn write_parquet<W: std::io::Write + Send>(
+    rows: &[FlatNode],
+    writer: W,
+) -> parquet::errors::Result<()> {
+    use std::sync::Arc;
+    use arrow_array::{ArrayRef, RecordBatch, StringArray, TimestampMillisecondArray, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, TimeUnit};
+    use parquet::arrow::ArrowWriter;
+
+    let schema = Arc::new(Schema::new(vec![
+        Field::new("path", DataType::Utf8, false),
+        Field::new("size", DataType::UInt64, false),
+        Field::new("type", DataType::Utf8, false),
+        Field::new(
+            "mtime",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+        Field::new(
+            "btime",
+            DataType::Timestamp(TimeUnit::Millisecond, None),
+            true,
+        ),
+        Field::new("id", DataType::UInt64, false),
+        Field::new("parent_id", DataType::UInt64, true),
+        Field::new("depth", DataType::UInt64, false),
+    ]));
+
+    let paths = StringArray::from_iter_values(rows.iter().map(|row| row.path.as_str()));
+    let sizes = UInt64Array::from_iter_values(rows.iter().map(|row| row.node.size));
+    let types = StringArray::from_iter_values(rows.iter().map(|row| row.node.node_type.as_str()));
+    let mtimes =
+        TimestampMillisecondArray::from_iter(rows.iter().map(|row| millis(row.node.mtime)));
+    let btimes =
+        TimestampMillisecondArray::from_iter(rows.iter().map(|row| millis(row.node.btime)));
+    let ids = UInt64Array::from_iter_values(rows.iter().map(|row| row.id));
+    let parent_ids = UInt64Array::from_iter(rows.iter().map(|row| row.parent_id));
+    let depths = UInt64Array::from_iter_values(rows.iter().map(|row| row.depth));
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(paths),
+        Arc::new(sizes),
+        Arc::new(types),
+        Arc::new(mtimes),
+        Arc::new(btimes),
+        Arc::new(ids),
+        Arc::new(parent_ids),
+        Arc::new(depths),
+    ];
+
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+    let mut writer = ArrowWriter::try_new(writer, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    Ok(())
+}
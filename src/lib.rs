@@ -0,0 +1,2560 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use log::{debug, warn};
+use rayon::prelude::*;
+use regex::RegexSet;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+use crate::progress::Progress;
+
+pub mod aggregate;
+pub mod cache;
+pub mod collapse;
+pub mod diff;
+#[cfg(unix)]
+pub mod fd;
+pub mod flat;
+pub mod manifest;
+pub mod output;
+pub mod progress;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+pub mod prune;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod snapshot;
+pub mod stats;
+pub mod walk;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum NodeType {
+    File,
+    Directory,
+    IgnoredDirectory,
+    /// A directory whose children were dropped to shrink the output. Its size
+    /// and counts still cover everything below it.
+    CollapsedDirectory,
+    /// A FIFO, socket or device. These are never opened, since reading a FIFO
+    /// blocks until a writer appears, and their size is recorded as 0.
+    Special,
+    /// A symlink that was not followed, either because following is off
+    /// (see [`IndexConfig::follow_symlinks`]) or because it is broken: its
+    /// target doesn't exist. Its size is recorded as 0.
+    Symlink {
+        /// The target as stored in the link, possibly relative to it.
+        #[serde(serialize_with = "serialize_slash_path")]
+        target: PathBuf,
+        /// The canonical path the link leads to, which may lie outside the
+        /// indexed root, or `None` if the link is broken.
+        #[serde(serialize_with = "serialize_slash_path_opt")]
+        resolved: Option<PathBuf>,
+    },
+}
+
+impl NodeType {
+    /// The variant name, as used in serialized output.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeType::File => "File",
+            NodeType::Directory => "Directory",
+            NodeType::IgnoredDirectory => "IgnoredDirectory",
+            NodeType::CollapsedDirectory => "CollapsedDirectory",
+            NodeType::Special => "Special",
+            NodeType::Symlink { .. } => "Symlink",
+        }
+    }
+}
+
+/// The name of a [`FileNode`]: an immutable string that is cheap to clone.
+/// Indexing with [`IndexConfig::intern_names`] makes nodes of the same name
+/// share one allocation. Dereferences to `str` and serializes as a string.
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Name(Arc<str>);
+
+impl Name {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::ops::Deref for Name {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl std::fmt::Debug for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::fmt::Debug::fmt(&*self.0, f)
+    }
+}
+
+impl std::fmt::Display for Name {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl From<String> for Name {
+    fn from(name: String) -> Self {
+        Name(name.into())
+    }
+}
+
+impl From<&str> for Name {
+    fn from(name: &str) -> Self {
+        Name(name.into())
+    }
+}
+
+impl AsRef<str> for Name {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<Path> for Name {
+    fn as_ref(&self) -> &Path {
+        Path::new(&*self.0)
+    }
+}
+
+impl AsRef<std::ffi::OsStr> for Name {
+    fn as_ref(&self) -> &std::ffi::OsStr {
+        self.0.as_ref().as_ref()
+    }
+}
+
+impl std::borrow::Borrow<str> for Name {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PartialEq<str> for Name {
+    fn eq(&self, other: &str) -> bool {
+        *self.0 == *other
+    }
+}
+
+impl PartialEq<&str> for Name {
+    fn eq(&self, other: &&str) -> bool {
+        *self.0 == **other
+    }
+}
+
+impl Serialize for Name {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Name {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Name::from)
+    }
+}
+
+/// Hands out one shared [`Name`] per distinct string. It is split into
+/// shards by hash so that threads interning at once rarely wait on each
+/// other.
+pub(crate) struct NamePool {
+    shards: Vec<Mutex<HashSet<Arc<str>>>>,
+}
+
+impl NamePool {
+    const SHARDS: usize = 64;
+
+    pub(crate) fn new() -> Self {
+        NamePool {
+            shards: (0..Self::SHARDS).map(|_| Mutex::default()).collect(),
+        }
+    }
+
+    pub(crate) fn intern(&self, name: String) -> Name {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        std::hash::Hash::hash(name.as_str(), &mut hasher);
+        let shard = &self.shards[std::hash::Hasher::finish(&hasher) as usize % Self::SHARDS];
+        let mut shard = shard.lock().unwrap();
+        if let Some(shared) = shard.get(name.as_str()) {
+            return Name(shared.clone());
+        }
+        let shared: Arc<str> = name.into();
+        shard.insert(shared.clone());
+        Name(shared)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FileNode {
+    pub name: Name,
+    pub size: u64,
+    /// The part of `size` that is indexed content, outside ignored
+    /// directories.
+    #[serde(default)]
+    pub indexed_size: u64,
+    /// The part of `size` inside ignored directories, so `indexed_size +
+    /// ignored_size == size`.
+    #[serde(default)]
+    pub ignored_size: u64,
+    /// Number of files in this subtree, including files inside ignored
+    /// directories whose structure is not stored.
+    #[serde(default)]
+    pub file_count: u64,
+    /// Number of directories in this subtree, not counting this one. Like
+    /// `file_count`, this includes directories inside ignored directories.
+    #[serde(default)]
+    pub dir_count: u64,
+    pub node_type: NodeType,
+    /// Last modification time, when the platform reports one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mtime: Option<SystemTime>,
+    /// Last access time, when the platform reports one. Filesystems mounted
+    /// with `noatime` never update it, and `relatime` (the Linux default) at
+    /// most once a day, so it is only a hint of how cold data is.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub atime: Option<SystemTime>,
+    /// Creation (birth) time, when the platform and filesystem report one.
+    /// Many Linux filesystems don't, leaving it `None`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub btime: Option<SystemTime>,
+    /// Canonical path of the target, when this node was reached through a
+    /// symlink that was followed. See [`FileNode::physical_path`] and
+    /// [`stats::double_counted`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub link_target: Option<PathBuf>,
+    /// BLAKE3 hex digest of the file contents, see [`FileNode::compute_hashes`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// BLAKE3 hex digest of a directory's contents, see
+    /// [`FileNode::compute_signatures`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signature: Option<String>,
+    /// Rendered path of this node, filled in by [`FileNode::assign_paths`]
+    /// unless the path style is [`PathStyle::Name`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub children: Vec<FileNode>,
+}
+
+impl FileNode {
+    pub fn new(name: String, size: u64, node_type: NodeType) -> Self {
+        Self::with_name(name.into(), size, node_type)
+    }
+
+    /// Like [`FileNode::new`], for a name that may already be shared.
+    pub fn with_name(name: Name, size: u64, node_type: NodeType) -> Self {
+        let file_count = match node_type {
+            NodeType::File => 1,
+            _ => 0,
+        };
+        let (indexed_size, ignored_size) = match node_type {
+            NodeType::IgnoredDirectory => (0, size),
+            _ => (size, 0),
+        };
+        FileNode {
+            name,
+            size,
+            indexed_size,
+            ignored_size,
+            file_count,
+            dir_count: 0,
+            node_type,
+            mtime: None,
+            atime: None,
+            btime: None,
+            link_target: None,
+            hash: None,
+            signature: None,
+            path: None,
+            children: Vec::new(),
+        }
+    }
+
+    pub fn add_child(&mut self, child: FileNode) {
+        self.size += child.size;
+        self.indexed_size += child.indexed_size;
+        self.ignored_size += child.ignored_size;
+        self.file_count += child.file_count;
+        self.dir_count += child.dir_count;
+        if matches!(
+            child.node_type,
+            NodeType::Directory | NodeType::IgnoredDirectory | NodeType::CollapsedDirectory
+        ) {
+            self.dir_count += 1;
+        }
+        self.children.push(child);
+    }
+
+    /// Size plus a fixed `block_overhead` charged for every file in the
+    /// subtree, approximating the real cost of storing or backing up many
+    /// small files.
+    pub fn weighted_cost(&self, block_overhead: u64) -> u64 {
+        self.size + self.file_count * block_overhead
+    }
+
+    /// Hashes every file in the tree in parallel, storing the digest in
+    /// `hash`. `root` is the path the tree was indexed from. Files that can't
+    /// be read are logged and left without a hash.
+    pub fn compute_hashes(&mut self, root: &Path) {
+        self.compute_hashes_with(root, None);
+    }
+
+    /// Like [`FileNode::compute_hashes`], adding every byte read to
+    /// `progress` as it is read, so that one huge file moves it along too.
+    /// Its total is best the sum of the file sizes, see
+    /// [`FileNode::file_bytes`].
+    pub fn compute_hashes_with(&mut self, root: &Path, progress: Option<&Progress>) {
+        fn hash_node(node: &mut FileNode, path: &Path, progress: Option<&Progress>) {
+            match node.node_type {
+                NodeType::File => match hash_file_counted(path, progress) {
+                    Ok(hash) => node.hash = Some(hash),
+                    Err(err) => warn!("failed to hash {}: {}", path.display(), err),
+                },
+                NodeType::Directory => node.children.par_iter_mut().for_each(|child| {
+                    let child_path = path.join(&child.name);
+                    hash_node(child, &child_path, progress);
+                }),
+                NodeType::IgnoredDirectory
+                | NodeType::CollapsedDirectory
+                | NodeType::Special
+                | NodeType::Symlink { .. } => {}
+            }
+        }
+
+        hash_node(self, root, progress);
+    }
+
+    /// Total size of the files stored in the tree, which is what hashing
+    /// reads. Unlike `size`, this leaves out ignored and collapsed
+    /// directories.
+    pub fn file_bytes(&self) -> u64 {
+        self.files().map(|(_, file)| file.size).sum()
+    }
+
+    /// Computes the `signature` of every directory, bottom-up, from its
+    /// children sorted by name: each child's name, type, size and file count,
+    /// plus a file's `hash`, a subdirectory's signature or a symlink's target.
+    /// A directory's own name is left out, so two directories with the same
+    /// contents under different names get the same signature, and comparing
+    /// signatures compares whole subtrees. Without hashes (see
+    /// [`FileNode::compute_hashes`]) the signature only covers names and
+    /// sizes, so files edited in place without changing size go unnoticed.
+    /// Ignored and collapsed directories, whose contents aren't stored, get
+    /// no signature and count only by their totals.
+    pub fn compute_signatures(&mut self) {
+        fn field(hasher: &mut blake3::Hasher, bytes: &[u8]) {
+            hasher.update(&(bytes.len() as u64).to_le_bytes());
+            hasher.update(bytes);
+        }
+
+        if !matches!(self.node_type, NodeType::Directory) {
+            return;
+        }
+        self.children
+            .par_iter_mut()
+            .for_each(FileNode::compute_signatures);
+        let mut children: Vec<&FileNode> = self.children.iter().collect();
+        children.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        let mut hasher = blake3::Hasher::new();
+        for child in children {
+            field(&mut hasher, child.name.as_bytes());
+            field(&mut hasher, child.node_type.as_str().as_bytes());
+            hasher.update(&child.size.to_le_bytes());
+            hasher.update(&child.file_count.to_le_bytes());
+            let content = match &child.node_type {
+                NodeType::Symlink { target, .. } => Some(target.to_string_lossy().into_owned()),
+                NodeType::Directory => child.signature.clone(),
+                _ => child.hash.clone(),
+            };
+            field(&mut hasher, content.unwrap_or_default().as_bytes());
+        }
+        self.signature = Some(hasher.finalize().to_hex().to_string());
+    }
+
+    /// Looks up a descendant by its `/`-separated path relative to this node,
+    /// e.g. `src/bin`. Leading, trailing and repeated slashes are ignored, so
+    /// `""` and `"/"` return this node.
+    pub fn get(&self, path: &str) -> Option<&FileNode> {
+        path.split('/')
+            .filter(|segment| !segment.is_empty() && *segment != ".")
+            .try_fold(self, |node, segment| {
+                node.children.iter().find(|child| child.name == segment)
+            })
+    }
+
+    /// Where the node at `path` (relative, as for [`FileNode::get`]) physically
+    /// is when it is at or below a followed directory symlink: the
+    /// [`FileNode::link_target`] of the innermost such link on the way,
+    /// joined with the rest of `path`. A link whose target couldn't be
+    /// canonicalized while indexing has none, so the next link up is used
+    /// instead. `None` if no link on the way has a target, or there is no node
+    /// at `path`.
+    pub fn physical_path(&self, path: &str) -> Option<PathBuf> {
+        let segments: Vec<&str> = path
+            .split('/')
+            .filter(|segment| !segment.is_empty() && *segment != ".")
+            .collect();
+        let mut node = self;
+        let mut innermost = None;
+        for (depth, segment) in segments.iter().enumerate() {
+            node = node.children.iter().find(|child| child.name == *segment)?;
+            if let Some(target) = &node.link_target {
+                innermost = Some((target, depth + 1));
+            }
+        }
+        let (target, depth) = innermost?;
+        Some(
+            segments[depth..]
+                .iter()
+                .fold(target.clone(), |physical, segment| physical.join(segment)),
+        )
+    }
+
+    /// Keeps only the files for which `keep(relative_path, file)` returns
+    /// true, dropping
+    /// directories left without files and recomputing sizes and counts.
+    /// Ignored and collapsed directories are dropped too, since their files
+    /// are not stored individually. The root itself is always kept.
+    pub fn retain_files<F: Fn(&str, &FileNode) -> bool>(&mut self, keep: &F) {
+        fn retain<F: Fn(&str, &FileNode) -> bool>(
+            node: &mut FileNode,
+            path: &str,
+            keep: &F,
+        ) -> bool {
+            match node.node_type {
+                NodeType::File => keep(path, node),
+                NodeType::IgnoredDirectory
+                | NodeType::CollapsedDirectory
+                | NodeType::Special
+                | NodeType::Symlink { .. } => false,
+                NodeType::Directory => {
+                    let children = std::mem::take(&mut node.children);
+                    node.size = 0;
+                    node.indexed_size = 0;
+                    node.ignored_size = 0;
+                    node.file_count = 0;
+                    node.dir_count = 0;
+                    for mut child in children {
+                        let child_path = join_relative(path, &child.name);
+                        if retain(&mut child, &child_path, keep) {
+                            node.add_child(child);
+                        }
+                    }
+                    !node.children.is_empty()
+                }
+            }
+        }
+
+        retain(self, "", keep);
+    }
+
+    /// Sums the sizes of the leaves (everything but plain directories) below
+    /// this node, without trusting the sizes accumulated on directories.
+    pub fn leaf_size(&self) -> u64 {
+        match self.node_type {
+            NodeType::Directory => self.children.iter().map(FileNode::leaf_size).sum(),
+            _ => self.size,
+        }
+    }
+
+    /// Recomputes every directory's size from its leaves and returns the
+    /// directories whose accumulated `size` disagrees.
+    pub fn verify_sizes(&self) -> Vec<SizeMismatch> {
+        fn verify(node: &FileNode, path: &str, mismatches: &mut Vec<SizeMismatch>) -> u64 {
+            let recomputed = match node.node_type {
+                NodeType::Directory => node
+                    .children
+                    .iter()
+                    .map(|child| verify(child, &join_relative(path, &child.name), mismatches))
+                    .sum(),
+                _ => return node.size,
+            };
+            if recomputed != node.size {
+                mismatches.push(SizeMismatch {
+                    path: path.to_string(),
+                    stored: node.size,
+                    recomputed,
+                });
+            }
+            recomputed
+        }
+
+        let mut mismatches = Vec::new();
+        verify(self, "", &mut mismatches);
+        mismatches
+    }
+
+    /// Sorts the children of every directory by descending size, keeping the
+    /// tree structure, so the heaviest subtrees come first. Equal sizes are
+    /// ordered by name so the output is deterministic.
+    pub fn sort_by_size_desc(&mut self) {
+        self.children
+            .sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.name.cmp(&b.name)));
+        for child in &mut self.children {
+            child.sort_by_size_desc();
+        }
+    }
+
+    /// Replaces every name with a sequential placeholder (`dir_0001`,
+    /// `file_0002.rs`), keeping file extensions so reports by extension still
+    /// work. Placeholders are assigned in tree order, so the structure stays
+    /// coherent within one run.
+    pub fn redact_names(&mut self) {
+        fn redact(node: &mut FileNode, dirs: &mut usize, files: &mut usize) {
+            if let NodeType::Symlink { target, resolved } = &mut node.node_type {
+                // The targets would leak real names just like the link's own.
+                *target = PathBuf::new();
+                if let Some(resolved) = resolved {
+                    *resolved = PathBuf::new();
+                }
+            }
+            if let NodeType::File | NodeType::Special | NodeType::Symlink { .. } = node.node_type {
+                *files += 1;
+                node.name = match Path::new(&node.name).extension() {
+                    Some(extension) => {
+                        format!("file_{:04}.{}", files, extension.to_string_lossy()).into()
+                    }
+                    None => format!("file_{:04}", files).into(),
+                };
+            } else {
+                *dirs += 1;
+                node.name = format!("dir_{:04}", dirs).into();
+            }
+            for child in &mut node.children {
+                redact(child, dirs, files);
+            }
+        }
+
+        redact(self, &mut 0, &mut 0);
+    }
+
+    /// Sets `path` on every node of the tree according to `style`. `root` is
+    /// the path the tree was indexed from.
+    pub fn assign_paths(&mut self, root: &Path, style: PathStyle) {
+        fn assign(node: &mut FileNode, root: &Path, style: PathStyle, relative: &str) {
+            node.path = Some(style.render(root, relative));
+            for child in &mut node.children {
+                let child_relative = join_relative(relative, &child.name);
+                assign(child, root, style, &child_relative);
+            }
+        }
+
+        if style != PathStyle::Name {
+            assign(self, root, style, "");
+        }
+    }
+}
+
+/// A directory whose accumulated size differs from the sum of its leaves.
+#[derive(Debug)]
+pub struct SizeMismatch {
+    /// Path relative to the root, see [`PathStyle::render`].
+    pub path: String,
+    pub stored: u64,
+    pub recomputed: u64,
+}
+
+/// How node paths are rendered, applied uniformly to every output.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PathStyle {
+    /// Relative to the indexed root, e.g. `src/main.rs`. The root is `.`.
+    #[default]
+    Relative,
+    /// The indexed root joined with the relative path.
+    Absolute,
+    /// The bare file or directory name.
+    Name,
+}
+
+impl PathStyle {
+    /// Renders a `/`-separated path relative to `root` (empty for the root
+    /// itself) in this style.
+    pub fn render(&self, root: &Path, relative: &str) -> String {
+        match self {
+            PathStyle::Relative if relative.is_empty() => ".".to_string(),
+            PathStyle::Relative => relative.to_string(),
+            PathStyle::Absolute if relative.is_empty() => slash_path(root),
+            PathStyle::Absolute => slash_path(&root.join(relative)),
+            PathStyle::Name => match relative.rsplit('/').next() {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => root
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| slash_path(root)),
+            },
+        }
+    }
+}
+
+impl std::str::FromStr for PathStyle {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "relative" => Ok(PathStyle::Relative),
+            "absolute" => Ok(PathStyle::Absolute),
+            "name" => Ok(PathStyle::Name),
+            _ => Err(format!("unknown path style: {}", s)),
+        }
+    }
+}
+
+/// `root` relative to its ancestor `base`, to render paths from `base`
+/// instead of from the indexed root: passed as the root to
+/// [`PathStyle::Absolute`], it gives `project/src` for the `src` directory of
+/// `/var/data/project` with a base of `/var/data`. Fails with
+/// [`io::ErrorKind::InvalidInput`] unless `base` is a proper ancestor of
+/// `root`.
+///
+/// ```
+/// # use std::path::Path;
+/// use fs_index::{strip_base, PathStyle};
+///
+/// let root = strip_base(Path::new("/var/data/project"), Path::new("/var/data"))?;
+/// assert_eq!(PathStyle::Absolute.render(&root, ""), "project");
+/// assert_eq!(PathStyle::Absolute.render(&root, "src/main.rs"), "project/src/main.rs");
+/// assert!(strip_base(Path::new("/var/data"), Path::new("/var/data")).is_err());
+/// assert!(strip_base(Path::new("/var/database"), Path::new("/var/data")).is_err());
+/// # Ok::<(), std::io::Error>(())
+/// ```
+pub fn strip_base(root: &Path, base: &Path) -> io::Result<PathBuf> {
+    match root.strip_prefix(base) {
+        Ok(relative) if !relative.as_os_str().is_empty() => Ok(relative.to_path_buf()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "base path {} is not a parent of {}",
+                base.display(),
+                root.display()
+            ),
+        )),
+    }
+}
+
+/// Renders `path` with `/` separators on every platform, so output written
+/// on Windows compares equal to output written elsewhere. Elsewhere `\\` is
+/// an ordinary file name character and is kept.
+///
+/// ```
+/// # use std::path::Path;
+/// assert_eq!(fs_index::slash_path(Path::new("src/bin")), "src/bin");
+/// ```
+pub fn slash_path(path: &Path) -> String {
+    with_slashes(&path.to_string_lossy(), cfg!(windows))
+}
+
+/// `path` with each `\\` replaced by `/` when it is a `windows` separator.
+fn with_slashes(path: &str, windows: bool) -> String {
+    if windows {
+        path.replace('\\', "/")
+    } else {
+        path.to_string()
+    }
+}
+
+fn serialize_slash_path<S: serde::Serializer>(
+    path: &Path,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&slash_path(path))
+}
+
+fn serialize_slash_path_opt<S: serde::Serializer>(
+    path: &Option<PathBuf>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match path {
+        Some(path) => serializer.serialize_some(&slash_path(path)),
+        None => serializer.serialize_none(),
+    }
+}
+
+fn join_relative(parent: &str, name: &str) -> String {
+    if parent.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", parent, name)
+    }
+}
+
+/// The quantity reports use to rank nodes.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum SizeMetric {
+    /// Raw size in bytes.
+    #[default]
+    Bytes,
+    /// Size plus a per-file overhead, see [`FileNode::weighted_cost`].
+    Weighted { block_overhead: u64 },
+}
+
+impl SizeMetric {
+    pub fn of(&self, node: &FileNode) -> u64 {
+        match *self {
+            SizeMetric::Bytes => node.size,
+            SizeMetric::Weighted { block_overhead } => node.weighted_cost(block_overhead),
+        }
+    }
+}
+
+/// Options controlling how names are compared by `fuzzy_search`.
+///
+/// All flags default to `false`: matching is smart-case (case-insensitive
+/// unless the query contains an uppercase letter), names are compared
+/// exactly as stored and matches are reported at their logical paths.
+#[derive(Debug, Clone, Default)]
+pub struct SearchOptions {
+    pub ignore_case: bool,
+    pub normalize_unicode: bool,
+    /// Report matches at or below a followed directory symlink at their
+    /// physical path, see [`SearchOptions::render`].
+    pub resolve_symlinks: bool,
+}
+
+impl SearchOptions {
+    /// Renders the path of a match in `root` like [`PathStyle::render`], or,
+    /// with `resolve_symlinks`, as the absolute [`FileNode::physical_path`]
+    /// when it has one.
+    pub fn render(
+        &self,
+        root: &FileNode,
+        style: PathStyle,
+        root_path: &Path,
+        path: &str,
+    ) -> String {
+        if self.resolve_symlinks {
+            if let Some(physical) = root.physical_path(path) {
+                return slash_path(&physical);
+            }
+        }
+        style.render(root_path, path)
+    }
+}
+
+/// Converts a name to Unicode Normalization Form C, so that composed and
+/// decomposed spellings of the same character (as produced by macOS HFS+/APFS)
+/// compare equal.
+pub fn normalize_name(name: &str) -> String {
+    name.nfc().collect()
+}
+
+/// Builds a matcher from glob patterns such as `src/**/*.rs`. Patterns are
+/// matched against paths relative to the root, and `*` also matches `/`, so
+/// `*.rs` matches at any depth.
+pub fn build_globset<S: AsRef<str>>(patterns: &[S]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern.as_ref())?);
+    }
+    builder.build()
+}
+
+/// Returns the length of the file at `path`, following symlinks, like
+/// `fs::metadata(path)?.len()`. On Linux this is a `statx` call that asks
+/// only for the size, leaving filesystems free to skip computing the rest.
+/// Elsewhere, or where `statx` is unavailable, it falls back to
+/// `fs::metadata`. On a local ext4 disk it is no faster, since the standard
+/// library already uses `statx` and resolves names relative to the open
+/// directory; the `file_size` benchmark compares the two.
+pub fn file_size(path: &Path) -> io::Result<u64> {
+    #[cfg(all(target_os = "linux", any(target_env = "gnu", target_env = "musl")))]
+    {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let c_path = CString::new(path.as_os_str().as_bytes())
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+        let mut buf = std::mem::MaybeUninit::<libc::statx>::zeroed();
+        // SAFETY: `c_path` is NUL-terminated and `buf` is large enough for
+        // the kernel to write a `statx` struct into.
+        let ret = unsafe {
+            libc::statx(
+                libc::AT_FDCWD,
+                c_path.as_ptr(),
+                libc::AT_STATX_SYNC_AS_STAT,
+                libc::STATX_SIZE,
+                buf.as_mut_ptr(),
+            )
+        };
+        if ret == 0 {
+            // SAFETY: statx succeeded, so it filled in the struct.
+            let buf = unsafe { buf.assume_init() };
+            if buf.stx_mask & libc::STATX_SIZE != 0 {
+                return Ok(buf.stx_size);
+            }
+        } else {
+            let err = io::Error::last_os_error();
+            // Kernels before 4.11, or sandboxes filtering the syscall.
+            if !matches!(err.raw_os_error(), Some(libc::ENOSYS | libc::EPERM)) {
+                return Err(err);
+            }
+        }
+    }
+    Ok(fs::metadata(path)?.len())
+}
+
+/// Returns the space the file `metadata` describes takes on disk. On Unix
+/// this is its allocated blocks, counted in the 512-byte units of `st_blocks`
+/// whatever the filesystem's block size, which is less than its length on
+/// compressed or sparse files and more for files that don't fill their last
+/// block. Elsewhere it is the length, as block counts aren't exposed.
+pub fn allocated_size(metadata: &fs::Metadata) -> u64 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.blocks() * 512
+    }
+    #[cfg(not(unix))]
+    {
+        metadata.len()
+    }
+}
+
+/// The size recorded for a file, see [`IndexConfig::physical_size`].
+fn recorded_size(metadata: &fs::Metadata, physical: bool) -> u64 {
+    if physical {
+        allocated_size(metadata)
+    } else {
+        metadata.len()
+    }
+}
+
+/// Returns the BLAKE3 hex digest of a file's contents.
+pub fn hash_file(path: &Path) -> io::Result<String> {
+    hash_file_counted(path, None)
+}
+
+/// Like [`hash_file`], adding the bytes read to `progress` as it goes.
+fn hash_file_counted(path: &Path, progress: Option<&Progress>) -> io::Result<String> {
+    struct Counted<'a, R> {
+        inner: R,
+        progress: Option<&'a Progress>,
+    }
+
+    impl<R: io::Read> io::Read for Counted<'_, R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let read = self.inner.read(buf)?;
+            if let Some(progress) = self.progress {
+                progress.add(read as u64);
+            }
+            Ok(read)
+        }
+    }
+
+    let mut hasher = blake3::Hasher::new();
+    hasher.update_reader(Counted {
+        inner: File::open(path)?,
+        progress,
+    })?;
+    Ok(hasher.finalize().to_hex().to_string())
+}
+
+/// Reads a tree saved as JSON, decompressing it first if it is gzip or zstd
+/// compressed. Compression is recognized by the file's first bytes rather
+/// than its extension, and reading it requires the `compression` feature.
+/// A [`snapshot::Snapshot`] is read as its tree; use
+/// [`snapshot::load_snapshot`] to keep its metadata.
+pub fn load_tree(path: &Path) -> io::Result<FileNode> {
+    snapshot::load_snapshot(path).map(|(tree, _)| tree)
+}
+
+/// Parses the JSON in `path`, decompressed as for [`load_tree`].
+pub(crate) fn read_json<T: DeserializeOwned>(path: &Path) -> io::Result<T> {
+    const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+    const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+    let mut reader = BufReader::new(File::open(path)?);
+    let magic = reader.fill_buf()?;
+    let compression = if magic.starts_with(GZIP_MAGIC) {
+        Some("gzip")
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Some("zstd")
+    } else {
+        None
+    };
+    let value = match compression {
+        None => serde_json::from_reader(reader)?,
+        #[cfg(feature = "compression")]
+        Some("gzip") => serde_json::from_reader(flate2::bufread::MultiGzDecoder::new(reader))?,
+        #[cfg(feature = "compression")]
+        Some(_) => serde_json::from_reader(zstd::Decoder::with_buffer(reader)?)?,
+        #[cfg(not(feature = "compression"))]
+        Some(compression) => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{} is {} compressed, which needs the compression feature",
+                    path.display(),
+                    compression
+                ),
+            ))
+        }
+    };
+    Ok(value)
+}
+
+/// The ignore file read in every directory unless
+/// [`IndexConfig::ignore_files`] says otherwise.
+pub const DEFAULT_IGNORE_FILE: &str = ".gitignore";
+
+pub fn read_gitignore(path: &Path) -> io::Result<Gitignore> {
+    read_ignore_files(path, &[DEFAULT_IGNORE_FILE])
+}
+
+/// Reads the ignore files named `names` in the directory at `path` as one
+/// set of rules, in order, so a later file's rules override an earlier one's
+/// just as later lines of a single `.gitignore` override earlier ones.
+/// Missing files are skipped.
+pub fn read_ignore_files<S: AsRef<str>>(path: &Path, names: &[S]) -> io::Result<Gitignore> {
+    let mut builder = GitignoreBuilder::new(path);
+    for name in names {
+        let ignore_path = path.join(name.as_ref());
+        if ignore_path.exists() {
+            debug!("loading {}", ignore_path.display());
+            if let Some(err) = builder.add(&ignore_path) {
+                warn!("error in {}: {}", ignore_path.display(), err);
+            }
+        }
+    }
+    Ok(builder.build().unwrap())
+}
+
+/// The [`IndexConfig::ignore_rules`] as rules of the directory at `root`.
+pub(crate) fn inline_ignore(root: &Path, rules: &[String]) -> Gitignore {
+    let mut builder = GitignoreBuilder::new(root);
+    for rule in rules {
+        if let Err(err) = builder.add_line(None, rule) {
+            warn!("error in --ignore-rules: {}", err);
+        }
+    }
+    builder.build().unwrap()
+}
+
+/// How the size of a gitignored directory is computed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IgnoredSizePolicy {
+    /// Count everything below the directory.
+    #[default]
+    SumAll,
+    /// Honor `.gitignore` files found below the directory: what they ignore
+    /// is left out of the size, and `!` rules in deeper files re-include it.
+    ApplyNested,
+}
+
+impl std::str::FromStr for IgnoredSizePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sum-all" => Ok(IgnoredSizePolicy::SumAll),
+            "apply-nested" => Ok(IgnoredSizePolicy::ApplyNested),
+            _ => Err(format!("unknown ignored size policy: {}", s)),
+        }
+    }
+}
+
+/// The git repository `path` is in: the nearest of `path` itself and its
+/// ancestors that contains `.git`. `path` should be canonical, or the search
+/// stops at the first component that is not.
+pub fn find_repo_root(path: &Path) -> Option<&Path> {
+    path.ancestors()
+        .find(|ancestor| ancestor.join(".git").exists())
+}
+
+/// Reads the `.gitignore` files that git would apply to `path` from above
+/// it: those of every ancestor directory up to the enclosing repository root
+/// (the nearest ancestor containing `.git`), outermost first. Returns none if
+/// `path` is not inside a repository.
+pub fn read_parent_gitignores(path: &Path) -> io::Result<Vec<Gitignore>> {
+    read_parent_ignore_files(path, &[DEFAULT_IGNORE_FILE])
+}
+
+/// Like [`read_parent_gitignores`], reading the ignore files named `names`
+/// in each directory as [`read_ignore_files`] does.
+pub fn read_parent_ignore_files<S: AsRef<str>>(
+    path: &Path,
+    names: &[S],
+) -> io::Result<Vec<Gitignore>> {
+    let path = fs::canonicalize(path)?;
+    let ancestors: Vec<&Path> = path.ancestors().skip(1).collect();
+    let Some(repo_root) = ancestors
+        .iter()
+        .position(|ancestor| ancestor.join(".git").exists())
+    else {
+        return Ok(Vec::new());
+    };
+    debug!(
+        "{} is inside the repository at {}",
+        path.display(),
+        ancestors[repo_root].display()
+    );
+    ancestors[..=repo_root]
+        .iter()
+        .rev()
+        .map(|ancestor| read_ignore_files(ancestor, names))
+        .collect()
+}
+
+/// Returns the total size and number of files under an ignored directory.
+/// Special files (FIFOs, sockets, devices) are neither counted nor opened.
+pub fn calculate_ignored_size(path: &Path, policy: IgnoredSizePolicy) -> io::Result<(u64, u64)> {
+    let sum = SumIgnored {
+        policy,
+        ignore_files: &[DEFAULT_IGNORE_FILE.to_string()],
+        progress: None,
+        open_dirs: None,
+        names_only: false,
+        retries: 0,
+        physical_size: false,
+        strict: false,
+    };
+    sum_ignored(path, &[], &sum).map(|(size, files, _)| (size, files))
+}
+
+/// Whether `err` says an entry no longer exists, which while walking a live
+/// directory like `/tmp` or a build output most likely means it was removed
+/// between listing its directory and reading it.
+pub(crate) fn vanished(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::NotFound
+}
+
+/// Logs why the entry at `path` is left out: quietly if it [`vanished`], as a
+/// warning for genuine failures such as denied permissions.
+pub(crate) fn skip_entry(path: impl std::fmt::Display, err: &io::Error) {
+    if vanished(err) {
+        debug!("{} vanished during the walk, skipping it", path);
+    } else {
+        warn!("skipping {}: {}", path, err);
+    }
+}
+
+/// An error [`IndexConfig::strict`] stopped a walk at, with the entry it
+/// happened on.
+#[derive(Debug)]
+struct EntryError {
+    path: String,
+    source: io::Error,
+}
+
+impl std::fmt::Display for EntryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.path, self.source)
+    }
+}
+
+impl std::error::Error for EntryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// `err` as it fails a strict walk: with the `path` of the entry, unless an
+/// entry further down already named itself, and of the same kind.
+pub(crate) fn entry_error(path: impl std::fmt::Display, err: io::Error) -> io::Error {
+    if err.get_ref().is_some_and(|inner| inner.is::<EntryError>()) {
+        return err;
+    }
+    io::Error::new(
+        err.kind(),
+        EntryError {
+            path: path.to_string(),
+            source: err,
+        },
+    )
+}
+
+/// What stays the same while summing one ignored directory.
+struct SumIgnored<'a> {
+    policy: IgnoredSizePolicy,
+    /// The ignore files to look for.
+    ignore_files: &'a [String],
+    progress: Option<&'a Progress>,
+    open_dirs: Option<&'a OpenDirs>,
+    /// Count files without a stat and size 0.
+    names_only: bool,
+    retries: u32,
+    physical_size: bool,
+    strict: bool,
+}
+
+/// `nested` holds the ignore files found so far below the ignored directory,
+/// outermost first.
+fn sum_ignored(
+    path: &Path,
+    nested: &[&Gitignore],
+    sum: &SumIgnored,
+) -> io::Result<(u64, u64, u64)> {
+    let SumIgnored {
+        policy,
+        ignore_files,
+        progress,
+        open_dirs,
+        names_only,
+        retries,
+        physical_size,
+        strict,
+    } = *sum;
+    let entries = list_dir(path, open_dirs, retries)?
+        .into_iter()
+        .collect::<io::Result<Vec<_>>>()?;
+    let local = if policy == IgnoredSizePolicy::ApplyNested
+        && entries
+            .iter()
+            .any(|entry| is_ignore_file(ignore_files, entry))
+    {
+        Some(read_ignore_files(path, ignore_files)?)
+    } else {
+        None
+    };
+    let mut stack = nested.to_vec();
+    stack.extend(local.as_ref());
+
+    let mut total_size = 0;
+    let mut file_count = 0;
+    let mut dir_count = 0;
+    if let Some(progress) = progress {
+        progress.add(entries.len() as u64);
+    }
+    for entry in entries {
+        // The entry's type usually comes from the directory listing itself,
+        // so only files, whose length is needed, cost a stat.
+        let file_type = entry.file_type()?;
+        if is_ignored(&stack, &entry.path(), file_type.is_dir()) {
+            debug!(
+                "{} matched a nested gitignore, not counting it",
+                entry.path().display()
+            );
+            continue;
+        }
+        if file_type.is_file() && names_only {
+            file_count += 1;
+        } else if file_type.is_file() {
+            match with_retries(retries, &entry.path(), || entry.metadata()) {
+                Ok(metadata) => {
+                    total_size += recorded_size(&metadata, physical_size);
+                    file_count += 1;
+                }
+                Err(err) if strict => return Err(entry_error(entry.path().display(), err)),
+                Err(err) if vanished(&err) => skip_entry(entry.path().display(), &err),
+                Err(err) => return Err(err),
+            }
+        } else if file_type.is_dir() {
+            match sum_ignored(&entry.path(), &stack, sum) {
+                Ok((size, files, dirs)) => {
+                    total_size += size;
+                    file_count += files;
+                    dir_count += dirs + 1;
+                }
+                Err(err) if strict => return Err(entry_error(entry.path().display(), err)),
+                Err(err) if vanished(&err) => skip_entry(entry.path().display(), &err),
+                Err(err) => return Err(err),
+            }
+        }
+    }
+    Ok((total_size, file_count, dir_count))
+}
+
+/// How many times listing a directory is retried when the process is out of
+/// file descriptors, waiting twice as long each time from 1 ms.
+const FD_RETRIES: u32 = 6;
+
+/// The wait before the first retry of [`IndexConfig::io_retries`], doubled
+/// for each one after.
+const IO_RETRY_BACKOFF: Duration = Duration::from_millis(10);
+
+/// Whether `err` may pass if the call is simply made again, as on a flaky
+/// network mount. A missing entry or denied permission won't.
+fn is_transient(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock
+    )
+}
+
+/// Called by [`with_retries`] with the path of every entry about to be read,
+/// so tests can change the tree between listing a directory and reading
+/// its entries.
+#[cfg(test)]
+static BEFORE_READ: Mutex<Option<ReadHook>> = Mutex::new(None);
+
+#[cfg(test)]
+type ReadHook = Box<dyn Fn(&Path) + Send>;
+
+/// Runs `op` on the entry at `path`, retrying up to `retries` times, with
+/// exponential backoff up to about 10 s, while it fails [`is_transient`]ly.
+pub(crate) fn with_retries<T>(
+    retries: u32,
+    path: &Path,
+    mut op: impl FnMut() -> io::Result<T>,
+) -> io::Result<T> {
+    #[cfg(test)]
+    if let Some(before_read) = &*BEFORE_READ.lock().unwrap() {
+        before_read(path);
+    }
+    let mut attempt = 0;
+    loop {
+        match op() {
+            Err(err) if attempt < retries && is_transient(&err) => {
+                let backoff = IO_RETRY_BACKOFF * (1 << attempt.min(10));
+                debug!(
+                    "{} failed with {}, retrying in {:?}",
+                    path.display(),
+                    err,
+                    backoff
+                );
+                thread::sleep(backoff);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Caps how many directories are being listed at once, see
+/// [`IndexConfig::max_open_dirs`].
+#[derive(Debug)]
+struct OpenDirs {
+    limit: usize,
+    open: Mutex<usize>,
+    closed: Condvar,
+}
+
+impl OpenDirs {
+    fn new(limit: usize) -> Self {
+        OpenDirs {
+            limit: limit.max(1),
+            open: Mutex::new(0),
+            closed: Condvar::new(),
+        }
+    }
+
+    /// Runs `list` once fewer than `limit` other listings are running.
+    fn with<T>(&self, list: impl FnOnce() -> T) -> T {
+        let mut open = self.open.lock().unwrap();
+        while *open >= self.limit {
+            open = self.closed.wait(open).unwrap();
+        }
+        *open += 1;
+        drop(open);
+        let _listing = Listing(self);
+        list()
+    }
+}
+
+/// Releases an [`OpenDirs`] slot when dropped, so a listing that panics
+/// (and is caught by rayon) doesn't hold it for the rest of the walk.
+struct Listing<'a>(&'a OpenDirs);
+
+impl Drop for Listing<'_> {
+    fn drop(&mut self) {
+        *self.0.open.lock().unwrap() -= 1;
+        self.0.closed.notify_one();
+    }
+}
+
+/// Whether `err` means the process or system ran out of file descriptors,
+/// which passes once other handles are closed.
+fn is_out_of_fds(err: &io::Error) -> bool {
+    #[cfg(unix)]
+    let codes = [libc::EMFILE, libc::ENFILE];
+    // ERROR_TOO_MANY_OPEN_FILES
+    #[cfg(windows)]
+    let codes = [4];
+    #[cfg(not(any(unix, windows)))]
+    let codes: [i32; 0] = [];
+    err.raw_os_error().is_some_and(|code| codes.contains(&code))
+}
+
+/// Reads the whole listing of `path`, so its handle is closed before any
+/// entry is looked at. Runs under `open_dirs` if given, and backs off and
+/// retries while out of file descriptors instead of failing the directory.
+/// Transient errors are retried up to `retries` times, see [`with_retries`].
+fn list_dir(
+    path: &Path,
+    open_dirs: Option<&OpenDirs>,
+    retries: u32,
+) -> io::Result<Vec<io::Result<fs::DirEntry>>> {
+    let list = || {
+        let mut attempt = 0;
+        loop {
+            let listed = with_retries(retries, path, || {
+                fs::read_dir(path).map(|entries| entries.collect::<Vec<_>>())
+            });
+            match listed {
+                Err(err) if is_out_of_fds(&err) && attempt < FD_RETRIES => {
+                    debug!(
+                        "out of file descriptors listing {}, retrying",
+                        path.display()
+                    );
+                    thread::sleep(Duration::from_millis(1 << attempt));
+                    attempt += 1;
+                }
+                listed => return listed,
+            }
+        }
+    };
+    match open_dirs {
+        Some(open_dirs) => open_dirs.with(list),
+        None => list(),
+    }
+}
+
+/// Whether `entry` is one of the ignore files named `ignore_files`. Going by
+/// the listing spares a stat in directories that have none.
+fn is_ignore_file(ignore_files: &[String], entry: &fs::DirEntry) -> bool {
+    let name = entry.file_name();
+    ignore_files
+        .iter()
+        .any(|ignore_file| name == ignore_file.as_str())
+}
+
+/// Matches `path` against a stack of `.gitignore` files, outermost first.
+/// The innermost one with a matching rule decides, as in git.
+fn is_ignored(stack: &[&Gitignore], path: &Path, is_dir: bool) -> bool {
+    for gitignore in stack.iter().rev() {
+        let matched = gitignore.matched(path, is_dir);
+        if matched.is_ignore() {
+            return true;
+        }
+        if matched.is_whitelist() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Directories with fewer entries than this are indexed sequentially, since
+/// handing a few entries to rayon costs more than it saves.
+pub const DEFAULT_PARALLEL_THRESHOLD: usize = 8;
+
+/// How many files one rayon task stats under [`SizeStrategy::Flat`] unless
+/// [`IndexConfig::size_chunk`] says otherwise.
+pub const DEFAULT_SIZE_CHUNK: usize = 256;
+
+/// When files are stat'ed for their sizes and times.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SizeStrategy {
+    /// While walking, by the task that lists each file's directory, so work
+    /// is split up per directory.
+    #[default]
+    Recursive,
+    /// After walking: the walk only lists directories, telling files apart
+    /// by the listing, and then every file is stat'ed in chunks of
+    /// [`IndexConfig::size_chunk`] spread evenly over the threads. This
+    /// balances trees where a few directories hold most of the files.
+    Flat,
+}
+
+impl std::str::FromStr for SizeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "recursive" => Ok(SizeStrategy::Recursive),
+            "flat" => Ok(SizeStrategy::Flat),
+            _ => Err(format!("unknown size strategy: {}", s)),
+        }
+    }
+}
+
+/// Options for indexing a tree, built up from defaults:
+///
+/// ```no_run
+/// use fs_index::IndexConfig;
+///
+/// let root = IndexConfig::new()
+///     .max_depth(3)
+///     .follow_symlinks(false)
+///     .index("some/folder".as_ref())?;
+/// println!("{} bytes", root.size);
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct IndexConfig {
+    normalize_unicode: bool,
+    parallel_threshold: usize,
+    ignored_size: IgnoredSizePolicy,
+    parent_ignore: bool,
+    ignore_files: Vec<String>,
+    ignore_rules: Vec<String>,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    max_open_dirs: Option<usize>,
+    exclude: RegexSet,
+    #[cfg(unix)]
+    owner: Option<u32>,
+    names_only: bool,
+    size_strategy: SizeStrategy,
+    size_chunk: usize,
+    intern_names: bool,
+    io_retries: u32,
+    physical_size: bool,
+    strict: bool,
+}
+
+impl Default for IndexConfig {
+    fn default() -> Self {
+        IndexConfig {
+            normalize_unicode: false,
+            parallel_threshold: DEFAULT_PARALLEL_THRESHOLD,
+            ignored_size: IgnoredSizePolicy::default(),
+            parent_ignore: true,
+            ignore_files: vec![DEFAULT_IGNORE_FILE.to_string()],
+            ignore_rules: Vec::new(),
+            max_depth: None,
+            follow_symlinks: true,
+            max_open_dirs: None,
+            exclude: RegexSet::empty(),
+            #[cfg(unix)]
+            owner: None,
+            names_only: false,
+            size_strategy: SizeStrategy::default(),
+            size_chunk: DEFAULT_SIZE_CHUNK,
+            intern_names: false,
+            io_retries: 0,
+            physical_size: false,
+            strict: false,
+        }
+    }
+}
+
+impl IndexConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Normalize names to Unicode NFC (off by default).
+    pub fn normalize_unicode(mut self, normalize_unicode: bool) -> Self {
+        self.normalize_unicode = normalize_unicode;
+        self
+    }
+
+    /// Index directories with fewer entries than this sequentially
+    /// (default [`DEFAULT_PARALLEL_THRESHOLD`]).
+    pub fn parallel_threshold(mut self, parallel_threshold: usize) -> Self {
+        self.parallel_threshold = parallel_threshold;
+        self
+    }
+
+    /// How gitignored directories are sized (default
+    /// [`IgnoredSizePolicy::SumAll`]).
+    pub fn ignored_size(mut self, ignored_size: IgnoredSizePolicy) -> Self {
+        self.ignored_size = ignored_size;
+        self
+    }
+
+    /// Apply the `.gitignore` files above the indexed folder, up to the
+    /// enclosing repository root, as git would (on by default). See
+    /// [`read_parent_gitignores`].
+    pub fn parent_ignore(mut self, parent_ignore: bool) -> Self {
+        self.parent_ignore = parent_ignore;
+        self
+    }
+
+    /// Read the ignore files named `names` in each directory instead of
+    /// only [`DEFAULT_IGNORE_FILE`], layered in order as
+    /// [`read_ignore_files`] describes: rules of later names override those
+    /// of earlier ones. Include `.gitignore` to keep honoring it. The names
+    /// also apply above the folder (see [`IndexConfig::parent_ignore`]) and
+    /// inside ignored directories sized with
+    /// [`IgnoredSizePolicy::ApplyNested`].
+    pub fn ignore_files<S: Into<String>>(mut self, names: impl IntoIterator<Item = S>) -> Self {
+        self.ignore_files = names.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Also ignore what the gitignore-syntax `rules`, one pattern per line,
+    /// match, as if they were in an ignore file at the root, e.g.
+    /// `"*.tmp\nbuild/"`. Rules of later calls override earlier ones, and the
+    /// ignore files of the tree and above it override all of them, the way
+    /// `.gitignore` files override `.git/info/exclude` in git.
+    ///
+    /// ```
+    /// use fs_index::IndexConfig;
+    ///
+    /// let tmp = tempfile::tempdir()?;
+    /// let dir = tmp.path();
+    /// std::fs::create_dir_all(dir.join("a/b/build"))?;
+    /// for file in ["keep.rs", "x.tmp", "a/y.tmp", "a/b/z.tmp", "a/b/build/out"] {
+    ///     std::fs::write(dir.join(file), "data")?;
+    /// }
+    /// let root = IndexConfig::new()
+    ///     .parent_ignore(false)
+    ///     .ignore_rules("*.tmp\nbuild/")
+    ///     .index(dir)?;
+    /// assert_eq!(root.files().map(|(path, _)| path).collect::<Vec<_>>(), ["keep.rs"]);
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn ignore_rules(mut self, rules: &str) -> Self {
+        self.ignore_rules.extend(rules.lines().map(str::to_string));
+        self
+    }
+
+    /// Store no deeper than `depth` levels below the root: directories at
+    /// that depth become [`NodeType::CollapsedDirectory`] nodes, still sized
+    /// in full. Unlimited by default.
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Follow symlinks and index them as their target (on by default).
+    /// Otherwise every symlink below the root becomes a
+    /// [`NodeType::Symlink`] node.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// List at most `limit` directories at once (unlimited by default). Each
+    /// listing is read in full before its entries are indexed, so this also
+    /// bounds the directory handles open at a time. With or without a limit,
+    /// a listing that fails because the process is out of file descriptors
+    /// is retried a few times with backoff.
+    pub fn max_open_dirs(mut self, limit: usize) -> Self {
+        self.max_open_dirs = Some(limit);
+        self
+    }
+
+    /// Skip entries whose path relative to the root, `/`-separated, matches
+    /// any of `exclude`. Excluded entries are left out entirely: they are
+    /// neither descended into nor counted in any size. This is checked before
+    /// `.gitignore` rules, and never against the root itself.
+    pub fn exclude(mut self, exclude: RegexSet) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /// Skip entries below the root not owned by the user with id `uid`, so
+    /// other users' files on a shared machine are left out along with the
+    /// errors from reading them. Costs a stat per entry. Only on Unix.
+    #[cfg(unix)]
+    pub fn owner(mut self, uid: u32) -> Self {
+        self.owner = Some(uid);
+        self
+    }
+
+    /// Record only which entries exist (off by default). Files are told apart
+    /// by the directory listing rather than a stat of each, and get size 0
+    /// and no times, so every size and file time in the tree is 0 or
+    /// missing. Symlinks and directories are still stat'ed. Much faster on
+    /// large trees when only structure matters, e.g. to diff which files
+    /// appeared or disappeared.
+    pub fn names_only(mut self, names_only: bool) -> Self {
+        self.names_only = names_only;
+        self
+    }
+
+    /// When files are stat'ed (default [`SizeStrategy::Recursive`]). Summaries
+    /// without a tree, see [`IndexConfig::summarize`], always use
+    /// [`SizeStrategy::Recursive`].
+    pub fn size_strategy(mut self, size_strategy: SizeStrategy) -> Self {
+        self.size_strategy = size_strategy;
+        self
+    }
+
+    /// How many files one task stats under [`SizeStrategy::Flat`] (default
+    /// [`DEFAULT_SIZE_CHUNK`]). Smaller chunks balance better, larger ones
+    /// cost less to hand out.
+    pub fn size_chunk(mut self, size_chunk: usize) -> Self {
+        self.size_chunk = size_chunk.max(1);
+        self
+    }
+
+    /// Retry listing a directory or stat'ing an entry up to `retries` times
+    /// (none by default) when it fails with an error that may be transient:
+    /// `TimedOut`, `Interrupted` or `WouldBlock`, as flaky network mounts
+    /// report. Each retry waits twice as long as the one before, starting at
+    /// 10 ms. Entries that are missing or not permitted fail at once.
+    pub fn io_retries(mut self, retries: u32) -> Self {
+        self.io_retries = retries;
+        self
+    }
+
+    /// Fail on the first entry that can't be read (off by default), with an
+    /// error naming it, instead of logging a warning and leaving it out of
+    /// the tree and every total. Entries that vanish during the walk fail
+    /// too. The rest of the walk stops as soon as it can: entries not
+    /// started yet are skipped, while those already being indexed on other
+    /// threads are finished first. Without it, only an unreadable root
+    /// fails the walk.
+    pub fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
+
+    /// Record each file's [`allocated_size`], the space it takes on disk,
+    /// instead of its length (off by default). On compressed or deduplicated
+    /// filesystems such as ZFS or Btrfs this is what `df` sees, and can be far
+    /// below the length. Only on Unix; elsewhere lengths are recorded either
+    /// way. Like the default, directories' own blocks are not counted, so
+    /// totals still come out below `du` on directories of many entries.
+    pub fn physical_size(mut self, physical_size: bool) -> Self {
+        self.physical_size = physical_size;
+        self
+    }
+
+    /// Share one allocation among all nodes of the same [`Name`] (off by
+    /// default). Trees of millions of nodes repeat names like `index.js` or
+    /// `__init__.py` a lot, so this saves memory at the cost of a hash
+    /// lookup per entry while walking.
+    pub fn intern_names(mut self, intern_names: bool) -> Self {
+        self.intern_names = intern_names;
+        self
+    }
+
+    /// Indexes the tree at `path`. Each directory's own `.gitignore` is read
+    /// while walking.
+    pub fn index(&self, path: &Path) -> io::Result<FileNode> {
+        self.index_with(path, None, None)
+    }
+
+    /// Like [`IndexConfig::index`], but reuses subtrees of `cached` (the tree
+    /// a previous run indexed from the same `path`) whose directories are
+    /// unchanged, and counts indexed entries in `progress`. See [`cache`] for
+    /// what counts as unchanged.
+    pub fn index_with(
+        &self,
+        path: &Path,
+        cached: Option<&FileNode>,
+        progress: Option<&Progress>,
+    ) -> io::Result<FileNode> {
+        self.walk(path, cached, progress, true)
+    }
+
+    /// Walks the tree at `path` like [`IndexConfig::index`], but only keeps
+    /// the totals: the returned root has the size, file count and times of a
+    /// full index and no children. Each directory's children are dropped as
+    /// soon as they are added up, so memory stays proportional to the widest
+    /// directories rather than the whole tree.
+    pub fn summarize(&self, path: &Path, progress: Option<&Progress>) -> io::Result<FileNode> {
+        self.walk(path, None, progress, false)
+    }
+
+    fn walk(
+        &self,
+        path: &Path,
+        cached: Option<&FileNode>,
+        progress: Option<&Progress>,
+        retain: bool,
+    ) -> io::Result<FileNode> {
+        // Entries are matched against ignore rules by their path, and the
+        // anchored rules of the `.gitignore` files above the root only match
+        // paths below its canonical form.
+        let given = path;
+        let canonical = fs::canonicalize(given)?;
+        let path = canonical.as_path();
+        let mut parent_gitignores = vec![inline_ignore(path, &self.ignore_rules)];
+        if self.parent_ignore {
+            parent_gitignores.extend(read_parent_ignore_files(path, &self.ignore_files)?);
+        }
+        let walk = Walk {
+            config: self,
+            root: path,
+            progress,
+            retain,
+            open_dirs: self.max_open_dirs.map(OpenDirs::new),
+            defer_file_stats: self.size_strategy == SizeStrategy::Flat
+                && retain
+                && !self.names_only,
+            names: self.intern_names.then(NamePool::new),
+        };
+        let mut root = index_path(
+            path,
+            &parent_gitignores.iter().collect::<Vec<_>>(),
+            &walk,
+            0,
+            cached,
+        )?;
+        if walk.defer_file_stats {
+            stat_files_flat(&mut root, path, self)?;
+            if let Some(depth) = self.max_depth {
+                collapse_at_depth(&mut root, depth);
+            }
+        }
+        // The root keeps the name it was asked by, e.g. a symlink's own.
+        let mut name = node_name(given);
+        if self.normalize_unicode {
+            name = normalize_name(&name);
+        }
+        root.name = walk.name(name);
+        Ok(root)
+    }
+}
+
+/// What stays the same throughout one walk.
+struct Walk<'a> {
+    config: &'a IndexConfig,
+    /// The indexed root, which exclusions are matched relative to.
+    root: &'a Path,
+    progress: Option<&'a Progress>,
+    /// Whether directories keep their children once they are added up.
+    retain: bool,
+    open_dirs: Option<OpenDirs>,
+    /// Whether files are left unstat'ed for [`stat_files_flat`], which also
+    /// postpones collapsing at the maximum depth until they are sized.
+    defer_file_stats: bool,
+    /// Where names come from with [`IndexConfig::intern_names`].
+    names: Option<NamePool>,
+}
+
+impl Walk<'_> {
+    fn name(&self, name: String) -> Name {
+        match &self.names {
+            Some(pool) => pool.intern(name),
+            None => name.into(),
+        }
+    }
+}
+
+/// Stats the files the walk left unsized under [`SizeStrategy::Flat`], those
+/// without an mtime, in parallel chunks of `chunk_size`, then adds up the
+/// directories above them again. Files that vanished since are dropped, or
+/// fail the walk under [`IndexConfig::strict`].
+fn stat_files_flat(root: &mut FileNode, root_path: &Path, config: &IndexConfig) -> io::Result<()> {
+    fn collect<'a>(
+        node: &'a mut FileNode,
+        path: &Path,
+        files: &mut Vec<(PathBuf, &'a mut FileNode)>,
+    ) {
+        for child in node.children.iter_mut() {
+            let child_path = path.join(&child.name);
+            if matches!(child.node_type, NodeType::File) && child.mtime.is_none() {
+                files.push((child_path, child));
+            } else if matches!(child.node_type, NodeType::Directory) {
+                collect(child, &child_path, files);
+            }
+        }
+    }
+
+    /// Sums `node` up again from its children, dropping the files marked
+    /// vanished by a `file_count` of 0.
+    fn resum(node: &mut FileNode) {
+        let children = std::mem::take(&mut node.children);
+        node.size = 0;
+        node.indexed_size = 0;
+        node.ignored_size = 0;
+        node.file_count = 0;
+        node.dir_count = 0;
+        for mut child in children {
+            match child.node_type {
+                NodeType::Directory => resum(&mut child),
+                NodeType::File if child.file_count == 0 => continue,
+                _ => {}
+            }
+            node.add_child(child);
+        }
+    }
+
+    let mut files = Vec::new();
+    collect(root, root_path, &mut files);
+    debug!(
+        "statting {} files in chunks of {}",
+        files.len(),
+        config.size_chunk
+    );
+    files
+        .par_chunks_mut(config.size_chunk)
+        .try_for_each(|chunk| {
+            for (path, node) in chunk {
+                match with_retries(config.io_retries, path, || fs::metadata(&*path)) {
+                    Ok(metadata) => {
+                        node.size = recorded_size(&metadata, config.physical_size);
+                        node.indexed_size = node.size;
+                        node.mtime = metadata.modified().ok();
+                        node.atime = metadata.accessed().ok();
+                        node.btime = metadata.created().ok();
+                    }
+                    Err(err) if config.strict => return Err(entry_error(path.display(), err)),
+                    Err(err) => {
+                        skip_entry(path.display(), &err);
+                        node.file_count = 0;
+                    }
+                }
+            }
+            Ok(())
+        })?;
+    if matches!(root.node_type, NodeType::Directory) {
+        resum(root);
+    }
+    Ok(())
+}
+
+/// Collapses the directories `depth` levels below `node`, as the walk does
+/// for [`IndexConfig::max_depth`].
+fn collapse_at_depth(node: &mut FileNode, depth: usize) {
+    if !matches!(node.node_type, NodeType::Directory) {
+        return;
+    }
+    if depth == 0 {
+        node.children.clear();
+        node.node_type = NodeType::CollapsedDirectory;
+        return;
+    }
+    for child in &mut node.children {
+        collapse_at_depth(child, depth - 1);
+    }
+}
+
+/// The name of the node for `path`: its last component or, for a path that
+/// has none like `.` or `/`, that of the path it resolves to, and the path
+/// as written if that has none either.
+fn node_name(path: &Path) -> String {
+    let canonical;
+    let named = match path.file_name() {
+        Some(_) => path,
+        None => {
+            canonical = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+            &canonical
+        }
+    };
+    match named.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => path.display().to_string(),
+    }
+}
+
+/// `gitignores` holds the `.gitignore` files of every directory above `path`,
+/// outermost first, and `depth` is how far `path` is below the root.
+fn index_path(
+    path: &Path,
+    gitignores: &[&Gitignore],
+    walk: &Walk,
+    depth: usize,
+    cached: Option<&FileNode>,
+) -> io::Result<FileNode> {
+    let config = walk.config;
+    if let Some(progress) = walk.progress {
+        progress.add(1);
+    }
+    let mut name = node_name(path);
+    if config.normalize_unicode {
+        name = normalize_name(&name);
+    }
+    let name = walk.name(name);
+    // The root is indexed even if it is a symlink, since it was asked for.
+    if !config.follow_symlinks && depth > 0 && path.is_symlink() {
+        let target = fs::read_link(path)?;
+        debug!(
+            "{} is a symlink to {}, not following it",
+            path.display(),
+            target.display()
+        );
+        return Ok(FileNode::with_name(
+            name,
+            0,
+            NodeType::Symlink {
+                target,
+                resolved: fs::canonicalize(path).ok(),
+            },
+        ));
+    }
+    let metadata = match with_retries(config.io_retries, path, || fs::metadata(path)) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound && path.is_symlink() => {
+            let target = fs::read_link(path)?;
+            debug!(
+                "{} is a broken symlink to {}",
+                path.display(),
+                target.display()
+            );
+            return Ok(FileNode::with_name(
+                name,
+                0,
+                NodeType::Symlink {
+                    target,
+                    resolved: None,
+                },
+            ));
+        }
+        Err(err) => return Err(err),
+    };
+
+    if metadata.is_file() {
+        let size = recorded_size(&metadata, config.physical_size);
+        let mut node = FileNode::with_name(name, size, NodeType::File);
+        node.mtime = metadata.modified().ok();
+        node.atime = metadata.accessed().ok();
+        node.btime = metadata.created().ok();
+        Ok(node)
+    } else if !metadata.is_dir() {
+        debug!("{} is a special file, not opening it", path.display());
+        Ok(FileNode::with_name(name, 0, NodeType::Special))
+    } else {
+        // A followed link is indexed as its target, so note where that is
+        // for resolving paths below it later. Only directories are checked,
+        // which keeps this to one extra lstat per directory.
+        let link_target = if depth > 0 && path.is_symlink() {
+            fs::canonicalize(path).ok()
+        } else {
+            None
+        };
+        // Like the symlink check above, the root was asked for explicitly, so
+        // ignore rules only apply to what is inside it.
+        if depth > 0 && is_ignored(gitignores, path, true) {
+            debug!(
+                "{} matched gitignore, summing size without storing structure",
+                path.display()
+            );
+            let sum = SumIgnored {
+                policy: config.ignored_size,
+                ignore_files: &config.ignore_files,
+                progress: walk.progress,
+                open_dirs: walk.open_dirs.as_ref(),
+                names_only: config.names_only,
+                retries: config.io_retries,
+                physical_size: config.physical_size,
+                strict: config.strict,
+            };
+            let (size, file_count, dir_count) = sum_ignored(path, &[], &sum)?;
+            let mut ignored = FileNode::with_name(name, size, NodeType::IgnoredDirectory);
+            ignored.file_count = file_count;
+            ignored.dir_count = dir_count;
+            ignored.mtime = metadata.modified().ok();
+            ignored.atime = metadata.accessed().ok();
+            ignored.btime = metadata.created().ok();
+            ignored.link_target = link_target;
+            return Ok(ignored);
+        }
+        let mut node = match cached.filter(|cached| cache::is_fresh(cached, path, &metadata)) {
+            Some(cached) => {
+                debug!("{} is unchanged, reusing cached subtree", path.display());
+                if let Some(progress) = walk.progress {
+                    progress.add(cached.node_count() as u64 - 1);
+                }
+                cached.clone()
+            }
+            None => {
+                let mut node = index_dir(path, name, gitignores, walk, depth, cached)?;
+                node.mtime = metadata.modified().ok();
+                node.atime = metadata.accessed().ok();
+                node.btime = metadata.created().ok();
+                node
+            }
+        };
+        node.link_target = link_target;
+        if config.max_depth == Some(depth) && !walk.defer_file_stats {
+            node.children.clear();
+            node.node_type = NodeType::CollapsedDirectory;
+        }
+        Ok(node)
+    }
+}
+
+/// Walks the entries of the directory at `path`, which is not ignored.
+fn index_dir(
+    path: &Path,
+    name: Name,
+    gitignores: &[&Gitignore],
+    walk: &Walk,
+    depth: usize,
+    cached: Option<&FileNode>,
+) -> io::Result<FileNode> {
+    let config = walk.config;
+    let entries = list_dir(path, walk.open_dirs.as_ref(), config.io_retries)?;
+    // Looking for the file in the listing saves a stat per directory.
+    let has_gitignore = entries.iter().any(|entry| {
+        entry
+            .as_ref()
+            .is_ok_and(|entry| is_ignore_file(&config.ignore_files, entry))
+    });
+    let new_gitignore = if has_gitignore {
+        read_ignore_files(path, &config.ignore_files)?
+    } else {
+        Gitignore::empty()
+    };
+    let mut child_gitignores = gitignores.to_vec();
+    child_gitignores.push(&new_gitignore);
+    debug!("entering directory {}", path.display());
+    let mut node = FileNode::with_name(name, 0, NodeType::Directory);
+    let cached_children: HashMap<&str, &FileNode> = cached
+        .into_iter()
+        .flat_map(|cached| &cached.children)
+        .map(|child| (child.name.as_str(), child))
+        .collect();
+    let index_entry = |entry: io::Result<fs::DirEntry>| {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) if config.strict => return Err(entry_error(path.display(), err)),
+            Err(err) => {
+                warn!("skipping unreadable entry in {}: {}", path.display(), err);
+                return Ok(None);
+            }
+        };
+        let child_path = entry.path();
+        if !config.exclude.is_empty() {
+            let relative = child_path.strip_prefix(walk.root).unwrap_or(&child_path);
+            let relative = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect::<Vec<_>>()
+                .join("/");
+            if config.exclude.is_match(&relative) {
+                debug!("{} matched --exclude-regex", child_path.display());
+                return Ok(None);
+            }
+        }
+        #[cfg(unix)]
+        if let Some(uid) = config.owner {
+            use std::os::unix::fs::MetadataExt;
+            // DirEntry::metadata doesn't follow symlinks, so a link counts as
+            // its own owner's.
+            let owned = match entry.metadata() {
+                Ok(metadata) => metadata.uid() == uid,
+                Err(err) if config.strict => {
+                    return Err(entry_error(child_path.display(), err));
+                }
+                Err(_) => false,
+            };
+            if !owned {
+                debug!(
+                    "{} is not owned by uid {}, skipping it",
+                    child_path.display(),
+                    uid
+                );
+                return Ok(None);
+            }
+        }
+        // Ignored directories are still sized, so only files are skipped
+        // here. The listing tells them apart without a stat.
+        let is_dir = entry.file_type().is_ok_and(|file_type| file_type.is_dir());
+        if !is_dir && is_ignored(&child_gitignores, &child_path, false) {
+            debug!("{} matched gitignore, skipping it", child_path.display());
+            return Ok(None);
+        }
+        let mut child_name = entry.file_name().to_string_lossy().into_owned();
+        if config.normalize_unicode {
+            child_name = normalize_name(&child_name);
+        }
+        if (config.names_only || walk.defer_file_stats)
+            && entry.file_type().is_ok_and(|file_type| file_type.is_file())
+        {
+            if let Some(progress) = walk.progress {
+                progress.add(1);
+            }
+            return Ok(Some(FileNode::with_name(
+                walk.name(child_name),
+                0,
+                NodeType::File,
+            )));
+        }
+        match index_path(
+            &child_path,
+            &child_gitignores,
+            walk,
+            depth + 1,
+            cached_children.get(child_name.as_str()).copied(),
+        ) {
+            // index_path only checks directories for being links; the listing
+            // tells for files without a stat.
+            Ok(mut child)
+                if matches!(child.node_type, NodeType::File)
+                    && entry
+                        .file_type()
+                        .is_ok_and(|file_type| file_type.is_symlink()) =>
+            {
+                child.link_target = fs::canonicalize(&child_path).ok();
+                Ok(Some(child))
+            }
+            Ok(child) => Ok(Some(child)),
+            Err(err) if config.strict => Err(entry_error(child_path.display(), err)),
+            Err(err) => {
+                skip_entry(child_path.display(), &err);
+                Ok(None)
+            }
+        }
+    };
+
+    // Collecting into a result stops at the first error, which only
+    // happens under IndexConfig::strict, and lets rayon skip the entries
+    // not started yet.
+    let children: Vec<FileNode> = if entries.len() < config.parallel_threshold {
+        entries
+            .into_iter()
+            .filter_map(|entry| index_entry(entry).transpose())
+            .collect::<io::Result<_>>()?
+    } else {
+        entries
+            .into_par_iter()
+            .filter_map(|entry| index_entry(entry).transpose())
+            .collect::<io::Result<_>>()?
+    };
+
+    for child in children {
+        node.add_child(child);
+    }
+    if !walk.retain {
+        node.children = Vec::new();
+    }
+
+    Ok(node)
+}
+
+/// Returns the `/`-separated paths, relative to the root, of every node whose
+/// name fuzzy-matches `query`. Use [`PathStyle::render`] to display them.
+pub fn fuzzy_search(root: &FileNode, query: &str, options: &SearchOptions) -> Vec<String> {
+    fuzzy_search_scored(root, query, options)
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// Like [`fuzzy_search`], but also returns each match's score (higher is a
+/// better match). Results are in tree order, not sorted by score.
+pub fn fuzzy_search_scored(
+    root: &FileNode,
+    query: &str,
+    options: &SearchOptions,
+) -> Vec<(String, i64)> {
+    let mut matches = Vec::new();
+    fuzzy_search_each(root, query, options, |path, score| {
+        matches.push((path, score))
+    });
+    matches
+}
+
+/// Calls `on_match` with the path and score of each match as the tree is
+/// searched, in tree order, so a caller can show the first results before
+/// the whole tree has been visited.
+pub fn fuzzy_search_each(
+    root: &FileNode,
+    query: &str,
+    options: &SearchOptions,
+    on_match: impl FnMut(String, i64),
+) {
+    Searcher::new(options).each(root, query, on_match)
+}
+
+/// A matcher set up once from [`SearchOptions`], for running many queries.
+pub struct Searcher {
+    matcher: SkimMatcherV2,
+    normalize_unicode: bool,
+}
+
+impl Searcher {
+    pub fn new(options: &SearchOptions) -> Self {
+        let mut matcher = SkimMatcherV2::default();
+        if options.ignore_case {
+            matcher = matcher.ignore_case();
+        }
+        Searcher {
+            matcher,
+            normalize_unicode: options.normalize_unicode,
+        }
+    }
+
+    /// See [`fuzzy_search_each`].
+    pub fn each(&self, root: &FileNode, query: &str, mut on_match: impl FnMut(String, i64)) {
+        let query = self.query(query);
+        for (path, node) in root.all() {
+            if let Some(score) = self.matcher.fuzzy_match(&node.name, &query) {
+                on_match(path, score);
+            }
+        }
+    }
+
+    fn query(&self, query: &str) -> String {
+        if self.normalize_unicode {
+            normalize_name(query)
+        } else {
+            query.to_string()
+        }
+    }
+}
+
+/// A search match with the positions that matched, for highlighting.
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchMatch {
+    /// Path relative to the root, see [`PathStyle::render`].
+    pub path: String,
+    pub score: i64,
+    /// Indices of the matched characters (not bytes) in the node's name,
+    /// which is the last segment of `path`.
+    pub indices: Vec<usize>,
+}
+
+/// Like [`fuzzy_search_scored`], but also returns which characters of each
+/// name matched. Slower, so only use it when the indices are needed.
+pub fn fuzzy_search_indices(
+    root: &FileNode,
+    query: &str,
+    options: &SearchOptions,
+) -> Vec<SearchMatch> {
+    let searcher = Searcher::new(options);
+    let query = searcher.query(query);
+    root.all()
+        .filter_map(|(path, node)| {
+            searcher
+                .matcher
+                .fuzzy_indices(&node.name, &query)
+                .map(|(score, indices)| SearchMatch {
+                    path,
+                    score,
+                    indices,
+                })
+        })
+        .collect()
+}
+
+/// Paths relative to the root, in tree order, of the files whose name
+/// matches the glob `pattern`, like `find -name`, or with `full_path` whose
+/// relative path does, like `find -path`. In a full path, `*` also matches
+/// `/`, as in [`build_globset`]. Only names are compared, so nothing is read.
+pub fn glob_search(
+    root: &FileNode,
+    pattern: &str,
+    full_path: bool,
+) -> Result<Vec<String>, globset::Error> {
+    let glob = Glob::new(pattern)?.compile_matcher();
+    Ok(root
+        .files()
+        .filter(|(path, file)| {
+            if full_path {
+                glob.is_match(path)
+            } else {
+                glob.is_match(file.name.as_str())
+            }
+        })
+        .map(|(path, _)| path)
+        .collect())
+}
+
+/// Maps each file name that occurs in more than one directory to the paths,
+/// relative to the root and in tree order, of every file with that name.
+/// Only names are compared, so nothing is read.
+pub fn find_name_collisions(root: &FileNode) -> HashMap<String, Vec<String>> {
+    let mut by_name: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, node) in root.files() {
+        by_name.entry(node.name.to_string()).or_default().push(path);
+    }
+    by_name.retain(|_, paths| paths.len() > 1);
+    by_name
+}
+
+/// Entries of one directory whose names differ only in case, such as
+/// `README.md` and `readme.md`. They can't coexist on a case-insensitive
+/// filesystem.
+#[derive(Debug, Clone)]
+pub struct CaseCollision {
+    /// Directory path relative to the root, see [`PathStyle::render`].
+    pub dir: String,
+    /// Paths of the colliding entries relative to the root, in tree order.
+    pub paths: Vec<String>,
+}
+
+/// Finds every [`CaseCollision`] in the tree, directories in pre-order and
+/// the groups within a directory in the order of their first entry. Names
+/// are compared by their lowercase form.
+pub fn find_case_collisions(root: &FileNode) -> Vec<CaseCollision> {
+    let mut collisions = Vec::new();
+    for (dir, node) in root.dirs() {
+        let mut groups: Vec<Vec<&str>> = Vec::new();
+        let mut by_lowercase: HashMap<String, usize> = HashMap::new();
+        for child in &node.children {
+            let index = *by_lowercase
+                .entry(child.name.to_lowercase())
+                .or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+            groups[index].push(&child.name);
+        }
+        for names in groups.into_iter().filter(|names| names.len() > 1) {
+            collisions.push(CaseCollision {
+                dir: dir.clone(),
+                paths: names
+                    .into_iter()
+                    .map(|name| join_relative(&dir, name))
+                    .collect(),
+            });
+        }
+    }
+    collisions
+}
+
+/// Directories with identical contents, found by their signatures.
+#[derive(Debug, Clone)]
+pub struct DuplicateDirs {
+    /// The size of each copy.
+    pub size: u64,
+    /// Paths of the copies relative to the root, in tree order.
+    pub paths: Vec<String>,
+}
+
+/// Groups the directories that share a signature, largest first, which
+/// requires [`FileNode::compute_signatures`] to have run. Directories
+/// without files are left out, since all empty directories look alike. A
+/// group whose every copy sits inside copies of another group is left out
+/// too: when two directories are duplicates, so is everything inside them,
+/// and only the outermost pair is worth reporting.
+pub fn find_duplicate_dirs(root: &FileNode) -> Vec<DuplicateDirs> {
+    fn parent(path: &str) -> &str {
+        path.rsplit_once('/').map_or("", |(parent, _)| parent)
+    }
+
+    let mut groups: Vec<DuplicateDirs> = Vec::new();
+    let mut by_signature: HashMap<&str, usize> = HashMap::new();
+    for (path, dir) in root.dirs() {
+        let Some(signature) = dir.signature.as_deref() else {
+            continue;
+        };
+        if dir.file_count == 0 {
+            continue;
+        }
+        let index = *by_signature.entry(signature).or_insert_with(|| {
+            groups.push(DuplicateDirs {
+                size: dir.size,
+                paths: Vec::new(),
+            });
+            groups.len() - 1
+        });
+        groups[index].paths.push(path);
+    }
+    let duplicated: HashSet<&str> = groups
+        .iter()
+        .filter(|group| group.paths.len() > 1)
+        .flat_map(|group| group.paths.iter().map(String::as_str))
+        .collect();
+    let mut duplicates: Vec<DuplicateDirs> = groups
+        .iter()
+        .filter(|group| {
+            group.paths.len() > 1
+                && !group
+                    .paths
+                    .iter()
+                    .all(|path| duplicated.contains(parent(path)))
+        })
+        .cloned()
+        .collect();
+    duplicates.sort_by_key(|group| std::cmp::Reverse(group.size));
+    duplicates
+}
+
+/// The smallest file [`find_duplicate_files`] considers unless told
+/// otherwise.
+pub const DEFAULT_DEDUP_MIN_SIZE: u64 = 1;
+
+/// Groups files with identical contents, largest files first, as paths
+/// relative to the root in tree order. `root` is the path the tree was
+/// indexed from. Files are first bucketed by size, which the tree already
+/// holds, and only files sharing their size with another are hashed, so
+/// files of a unique size are never read. On most trees that is the bulk of
+/// them. A `hash` already in the tree (see [`FileNode::compute_hashes`]) is
+/// used instead of reading the file again. Files smaller than `min_size`
+/// are left out before bucketing, so they are neither read nor reported;
+/// [`DEFAULT_DEDUP_MIN_SIZE`] leaves out only empty files, which are all
+/// alike. Files that can't be read are logged and skipped.
+pub fn find_duplicate_files(root: &FileNode, root_path: &Path, min_size: u64) -> Vec<Vec<String>> {
+    let mut by_size: HashMap<u64, Vec<(String, &FileNode)>> = HashMap::new();
+    let mut files = 0;
+    for (path, file) in root.files() {
+        files += 1;
+        if file.size >= min_size {
+            by_size.entry(file.size).or_default().push((path, file));
+        }
+    }
+    let mut buckets: Vec<(u64, Vec<(String, &FileNode)>)> = by_size
+        .into_iter()
+        .filter(|(_, bucket)| bucket.len() > 1)
+        .collect();
+    debug!(
+        "hashing {} of {} files, in {} buckets of equal size",
+        buckets
+            .iter()
+            .map(|(_, bucket)| bucket.len())
+            .sum::<usize>(),
+        files,
+        buckets.len()
+    );
+    buckets.sort_unstable_by_key(|(size, _)| std::cmp::Reverse(*size));
+
+    buckets
+        .into_par_iter()
+        .flat_map_iter(|(_, bucket)| {
+            let mut groups: Vec<Vec<String>> = Vec::new();
+            let mut by_hash: HashMap<String, usize> = HashMap::new();
+            for (path, file) in bucket {
+                let hash = match &file.hash {
+                    Some(hash) => hash.clone(),
+                    None => match hash_file(&root_path.join(&path)) {
+                        Ok(hash) => hash,
+                        Err(err) => {
+                            warn!("failed to hash {}: {}", path, err);
+                            continue;
+                        }
+                    },
+                };
+                let index = *by_hash.entry(hash).or_insert_with(|| {
+                    groups.push(Vec::new());
+                    groups.len() - 1
+                });
+                groups[index].push(path);
+            }
+            groups.into_iter().filter(|paths| paths.len() > 1)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs [`with_retries`] on an operation that fails with `kind` the first
+    /// `failures` times, returning its result and how often it was called.
+    fn flaky(retries: u32, failures: u32, kind: io::ErrorKind) -> (io::Result<()>, u32) {
+        let mut calls = 0;
+        let result = with_retries(retries, Path::new("flaky"), || {
+            calls += 1;
+            if calls <= failures {
+                Err(kind.into())
+            } else {
+                Ok(())
+            }
+        });
+        (result, calls)
+    }
+
+    #[test]
+    fn entries_vanishing_after_the_listing_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path().to_path_buf();
+        let populate = || {
+            for (path, contents) in [
+                (".gitignore", "build/\n"),
+                ("data/a.txt", "abc"),
+                ("data/gone.txt", "12345"),
+                ("build/out", "xy"),
+                ("build/gone.txt", "12345"),
+            ] {
+                fs::create_dir_all(root.join(path).parent().unwrap()).unwrap();
+                fs::write(root.join(path), contents).unwrap();
+            }
+        };
+        // Paths are absolute in the path walk, and in the descriptor walk
+        // relative to the root but starting with `/`.
+        let remove_root = root.clone();
+        *BEFORE_READ.lock().unwrap() = Some(Box::new(move |path: &Path| {
+            if path.file_name() == Some("gone.txt".as_ref()) {
+                let relative = path.strip_prefix(&remove_root).unwrap_or(path);
+                let relative = relative.strip_prefix("/").unwrap_or(relative);
+                let _ = fs::remove_file(remove_root.join(relative));
+            }
+        }));
+
+        let config = IndexConfig::new().parent_ignore(false);
+        type IndexFn<'a> = Box<dyn Fn(&IndexConfig) -> io::Result<FileNode> + 'a>;
+        let mut walks: Vec<(&str, IndexFn)> = vec![
+            ("recursive", Box::new(|config| config.index(&root))),
+            (
+                "flat",
+                Box::new(|config| {
+                    config
+                        .clone()
+                        .size_strategy(SizeStrategy::Flat)
+                        .index(&root)
+                }),
+            ),
+        ];
+        #[cfg(unix)]
+        walks.push((
+            "descriptor",
+            Box::new(|config| {
+                use std::os::fd::AsFd;
+                config.index_dir_fd(fs::File::open(&root)?.as_fd(), "root")
+            }),
+        ));
+        for (walk, index) in walks {
+            populate();
+            let tree = index(&config).unwrap_or_else(|err| panic!("{} walk: {}", walk, err));
+            let names: Vec<_> = tree
+                .get("data")
+                .unwrap()
+                .children
+                .iter()
+                .map(|child| child.name.as_str())
+                .collect();
+            assert_eq!(names, ["a.txt"], "{} walk", walk);
+            let build = tree.get("build").unwrap();
+            assert_eq!(build.size, 2, "{} walk", walk);
+            assert_eq!(tree.file_count, 3, "{} walk", walk);
+
+            populate();
+            assert!(
+                index(&config.clone().strict(true)).is_err(),
+                "{} walk",
+                walk
+            );
+        }
+        *BEFORE_READ.lock().unwrap() = None;
+    }
+
+    #[test]
+    fn open_dirs_releases_the_slot_of_a_panicking_listing() {
+        let open_dirs = OpenDirs::new(1);
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            open_dirs.with(|| panic!("listing failed"))
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(*open_dirs.open.lock().unwrap(), 0);
+        // With the only slot still taken, this would wait forever.
+        assert_eq!(open_dirs.with(|| 1), 1);
+    }
+
+    #[test]
+    fn with_slashes_replaces_only_windows_separators() {
+        assert_eq!(with_slashes(r"src\bin\main.rs", true), "src/bin/main.rs");
+        assert_eq!(with_slashes(r"C:\data", true), "C:/data");
+        assert_eq!(with_slashes(r"back\slash", false), r"back\slash");
+        assert_eq!(with_slashes("src/bin", true), "src/bin");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn paths_render_with_slashes_on_windows() {
+        assert_eq!(slash_path(Path::new(r"src\bin")), "src/bin");
+        let root = Path::new(r"C:\data\project");
+        assert_eq!(PathStyle::Absolute.render(root, ""), "C:/data/project");
+        assert_eq!(
+            PathStyle::Absolute.render(root, "src/main.rs"),
+            "C:/data/project/src/main.rs"
+        );
+        assert_eq!(PathStyle::Name.render(root, ""), "project");
+        assert_eq!(
+            PathStyle::Relative.render(root, "src/main.rs"),
+            "src/main.rs"
+        );
+    }
+
+    #[test]
+    fn with_retries_retries_transient_errors() {
+        let (result, calls) = flaky(1, 1, io::ErrorKind::TimedOut);
+        assert!(result.is_ok());
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn with_retries_gives_up_after_the_last_retry() {
+        let (result, calls) = flaky(1, 2, io::ErrorKind::Interrupted);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::Interrupted);
+        assert_eq!(calls, 2);
+    }
+
+    #[test]
+    fn with_retries_fails_at_once_on_other_errors() {
+        let (result, calls) = flaky(3, 1, io::ErrorKind::NotFound);
+        assert_eq!(result.unwrap_err().kind(), io::ErrorKind::NotFound);
+        assert_eq!(calls, 1);
+    }
+}
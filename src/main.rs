@@ -1,164 +1,2151 @@
 use std::collections::HashSet;
 use std::fs::{self, File};
-use std::io::{self, BufRead, Read};
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
-use std::time::Instant;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
 use bytesize::ByteSize;
-use fuzzy_matcher::skim::SkimMatcherV2;
-use fuzzy_matcher::FuzzyMatcher;
-use ignore::gitignore::{Gitignore, GitignoreBuilder};
-use rayon::prelude::*;
+use fs_index::aggregate::Aggregate;
+use fs_index::cache::{CacheKey, TreeCache};
+use fs_index::diff::{diff_trees, size_deltas};
+use fs_index::manifest::{find_stale, read_manifest, verify_manifest, write_manifest};
+use fs_index::output::{Csv, Dot, Du, FlatMap, FlatPaths, Json, Ndjson, OutputFormat, Paths};
+use fs_index::progress::{count_entries, Progress};
+use fs_index::prune::{find_empty, find_suspicious_empty, remove_empty};
+use fs_index::snapshot::{load_snapshot, RunMetadata, Snapshot};
+use fs_index::stats::{
+    deepest_path, double_counted, fragmentation, hotspots, largest_dirs, type_summary, wide_dirs,
+};
+use fs_index::{
+    build_globset, find_case_collisions, find_duplicate_dirs, find_duplicate_files,
+    find_name_collisions, fuzzy_search, fuzzy_search_each, find_repo_root, glob_search, load_tree,
+    slash_path, strip_base, FileNode, IgnoredSizePolicy, IndexConfig, NodeType, PathStyle,
+    SearchOptions, Searcher, SizeMetric, SizeStrategy, DEFAULT_DEDUP_MIN_SIZE, DEFAULT_IGNORE_FILE,
+    DEFAULT_PARALLEL_THRESHOLD,
+};
+use log::debug;
+use regex::RegexSet;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Serialize, Deserialize)]
-enum NodeType {
-    File,
-    Directory,
-    IgnoredDirectory,
+#[derive(Clone, Copy, PartialEq)]
+enum ErrorFormat {
+    Text,
+    Json,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-struct FileNode {
-    name: String,
-    size: u64,
-    node_type: NodeType,
-    children: Vec<FileNode>,
+enum Format {
+    Json,
+    FlatMap,
+    Csv,
+    Ndjson,
+    Dot,
+    Paths,
+    Du,
+    #[cfg(feature = "parquet")]
+    Parquet,
+    #[cfg(feature = "yaml")]
+    Yaml,
+    #[cfg(feature = "protobuf")]
+    Protobuf,
 }
 
-impl FileNode {
-    fn new(name: String, size: u64, node_type: NodeType) -> Self {
-        FileNode {
-            name,
-            size,
-            node_type,
-            children: Vec::new(),
+impl Format {
+    fn default_output(&self) -> &'static str {
+        match self {
+            Format::Json | Format::FlatMap => "file_tree.json",
+            Format::Csv => "file_tree.csv",
+            Format::Ndjson => "file_tree.ndjson",
+            Format::Dot => "file_tree.dot",
+            Format::Paths => "file_tree.txt",
+            Format::Du => "file_tree.du.txt",
+            #[cfg(feature = "parquet")]
+            Format::Parquet => "file_tree.parquet",
+            #[cfg(feature = "yaml")]
+            Format::Yaml => "file_tree.yaml",
+            #[cfg(feature = "protobuf")]
+            Format::Protobuf => "file_tree.pb",
         }
     }
+}
+
+struct Args {
+    folder_path: Option<String>,
+    load: Option<String>,
+    merge_ndjson: Vec<String>,
+    top: usize,
+    get: Option<String>,
+    summary_only: bool,
+    fragmentation: bool,
+    type_summary: bool,
+    hotspots: Option<u64>,
+    wide_dirs: Option<usize>,
+    largest_dirs: Option<usize>,
+    exclude_ancestors: bool,
+    find_broken_symlinks: bool,
+    find_name_collisions: bool,
+    find_case_collisions: bool,
+    find_duplicate_dirs: bool,
+    find_duplicates: bool,
+    dedup_min_size: u64,
+    prune_empty: bool,
+    apply: bool,
+    find_empty_suspicious: Vec<String>,
+    search_query: String,
+    glob: Option<String>,
+    glob_path: bool,
+    search_options: SearchOptions,
+    print0: bool,
+    repl: bool,
+    size_metric: SizeMetric,
+    path_style: PathStyle,
+    base_path: Option<String>,
+    git_root: bool,
+    verify: bool,
+    fail_over: Option<u64>,
+    assert_matches: Option<String>,
+    explain_growth: Option<String>,
+    record_run: bool,
+    tree_options: Vec<String>,
+    newer_than_file: Option<String>,
+    not_accessed_since: Option<Duration>,
+    hash: bool,
+    dir_signatures: bool,
+    manifest: Option<String>,
+    verify_manifest: Option<String>,
+    check_manifest: Option<String>,
+    parallel_threshold: usize,
+    size_strategy: SizeStrategy,
+    size_chunk: Option<usize>,
+    max_open_dirs: Option<usize>,
+    io_retries: u32,
+    physical_size: bool,
+    strict: bool,
+    ignored_size: IgnoredSizePolicy,
+    progress: bool,
+    parent_ignore: bool,
+    ignore_files: Vec<String>,
+    ignore_rules: Vec<String>,
+    max_depth: Option<usize>,
+    follow_symlinks: bool,
+    owner_only: bool,
+    names_only: bool,
+    intern_names: bool,
+    cache: Option<String>,
+    format: Format,
+    json_compact: bool,
+    error_format: ErrorFormat,
+    no_config: bool,
+    flatmap_all: bool,
+    summary_record: bool,
+    output: Option<String>,
+    output_dir_stats: Option<String>,
+    redact: bool,
+    sort_size_desc: bool,
+    max_output_size: Option<u64>,
+    rollup_below: Option<u64>,
+    include: Vec<String>,
+    ext: Vec<String>,
+    exclude_regex: Vec<String>,
+    #[cfg(feature = "server")]
+    serve: Option<String>,
+}
+
+/// Flags that change which entries a tree holds or what is recorded about
+/// them, and whether each takes a value. These are what `--record-run`
+/// records and what trees compared with `--assert-matches` or
+/// `--explain-growth` should agree on.
+const TREE_OPTIONS: &[(&str, bool)] = &[
+    ("--summary-only", false),
+    ("--include", true),
+    ("--ext", true),
+    ("--exclude-regex", true),
+    ("--exclude-vcs", false),
+    ("--newer-than-file", true),
+    ("--not-accessed-since", true),
+    ("--hash", false),
+    ("--dir-signatures", false),
+    ("--ignored-size", true),
+    ("--no-parent-ignore", false),
+    ("--ignore-file", true),
+    ("--ignore-rules", true),
+    ("--max-depth", true),
+    ("--no-follow-symlinks", false),
+    ("--owner-only", false),
+    ("--physical-size", false),
+    ("--names-only", false),
+    ("--normalize-unicode", false),
+    ("--rollup-below", true),
+    ("--redact", false),
+    ("--sort", true),
+];
+
+/// How many of the largest files `--merge-ndjson` lists unless `--top` says
+/// otherwise.
+const DEFAULT_TOP: usize = 10;
+
+/// What `--exclude-vcs` adds to the `--exclude-regex` patterns: Git,
+/// Subversion and Mercurial metadata directories at any depth.
+const EXCLUDE_VCS: &str = r"(^|/)\.(git|svn|hg)$";
+
+/// Parses an age like `90d`: a whole number followed by `s`, `m`, `h`, `d`
+/// or `w`.
+fn parse_age(age: &str) -> Option<Duration> {
+    let split = age.len().checked_sub(1)?;
+    let (count, unit) = age.split_at_checked(split)?;
+    let seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+    Some(Duration::from_secs(
+        count.parse::<u64>().ok()?.checked_mul(seconds)?,
+    ))
+}
 
-    fn add_child(&mut self, child: FileNode) {
-        self.size += child.size;
-        self.children.push(child);
+/// Whether the access times in the tree look like they are not being
+/// updated: every file's is within a second of its modification time, as
+/// happens on filesystems mounted with `noatime`. Small trees are not judged.
+fn atimes_track_mtimes(root: &FileNode) -> bool {
+    let mut files = 0;
+    for (_, file) in root.files() {
+        let (Some(atime), Some(mtime)) = (file.atime, file.mtime) else {
+            continue;
+        };
+        let apart = atime
+            .duration_since(mtime)
+            .unwrap_or_else(|err| err.duration());
+        if apart >= Duration::from_secs(1) {
+            return false;
+        }
+        files += 1;
     }
+    files >= 10
 }
 
-fn read_gitignore(path: &Path) -> io::Result<Gitignore> {
-    let mut builder = GitignoreBuilder::new(path);
-    let gitignore_path = path.join(".gitignore");
-    if gitignore_path.exists() {
-        builder.add(gitignore_path);
+const OPTIONS: &str = "\
+Options:
+  --load <file>        Load a previously saved tree instead of indexing a folder
+  --merge-ndjson <file>
+                       Instead of a tree, report the combined totals, sizes by
+                       extension and largest files of NDJSON exports
+                       (repeatable)
+  --top <n>            With --merge-ndjson, list the <n> largest files
+                       (default: 10)
+  --format <format>    Output format: json (default), flatmap, csv, ndjson, dot,
+                       paths, du, parquet, yaml or protobuf (the last three
+                       require the features of that name)
+  --flatmap-all        Include directories, not just files, in flatmap output
+  --summary-record     Start csv and ndjson output with the root's path, total
+                       size and file count
+  --json-compact       Write JSON without indentation or newlines
+  --error-format <format>
+                       Report a failure as text (default) or as a JSON object
+                       on stderr
+  --no-config          Don't read options from .fsindex.toml
+  --output <file>      Write the output to <file> (default: file_tree.<ext>), or
+                       to stdout if <file> is -
+  --output-dir-stats <dir>
+                       Write each top-level directory's size in bytes to
+                       <dir>/<name>.size
+  --max-output-size <size>
+                       Collapse the smallest directories until the JSON
+                       output fits in <size>
+  --rollup-below <size>
+                       Replace the entries smaller than <size> in each
+                       directory with one (N small items) node
+  --get <path>         Print the size and immediate children of the node at <path>
+  --summary-only       Print only the total size, file count and time taken,
+                       without building the tree or writing output
+  --fragmentation      Print file size statistics for each directory
+  --type-summary       Print how many nodes of each type there are and how
+                       much of the total size each type accounts for
+  --hotspots <size>    List directories of at least <size> whose weight is not
+                       mostly in one subdirectory
+  --wide-dirs <n>      List directories with more than <n> immediate entries
+  --largest-dirs <n>   List the <n> largest directories by total size
+  --exclude-ancestors  With --largest-dirs, leave out directories inside one
+                       already listed
+  --find-broken-symlinks
+                       List symlinks whose target doesn't exist
+  --find-name-collisions
+                       List file names that occur in more than one directory
+  --find-case-collisions
+                       List entries whose names differ only in case within a
+                       directory
+  --find-duplicate-dirs
+                       List directories with identical contents (compared by
+                       name and size, or by content with --hash)
+  --find-duplicates    List files with identical contents, hashing only files
+                       that share their size with another
+  --dedup-min-size <size>
+                       With --find-duplicates, skip files smaller than <size>
+                       (default: 1B, skipping only empty files)
+  --find-empty-suspicious <ext>[,<ext>...]
+                       List zero-byte files with one of these extensions and
+                       exit with status 1 if there are any
+  --prune-empty        List zero-byte files and empty directories that could be
+                       deleted
+  --apply              With --prune-empty, actually delete them
+  --search <query>     Fuzzy search query to run against the tree (default: example)
+  --ignore-case        Match case-insensitively instead of smart-case
+  --normalize-unicode  Normalize names and the query to Unicode NFC
+  --resolve-symlinks   Report search results below a followed directory symlink
+                       at their physical path
+  --glob <pattern>     Instead of a fuzzy search, list the files whose name
+                       matches the glob <pattern>, e.g. '*.rs'
+  --glob-path          With --glob, match the path relative to the root
+                       instead of the name
+  --print0             Print result paths terminated by NUL instead of newline
+  --repl               Read queries from stdin, one per line, and answer each:
+                       a fuzzy query, :largest <n> or :path <path>
+  --paths <style>      Render paths as relative (default), absolute, name or
+                       git-root (relative to the enclosing git repository)
+  --base-path <dir>    Render paths relative to <dir>, a parent of the folder
+  --redact             Replace names with placeholders, keeping extensions
+  --sort size-desc     List each directory's children largest first
+  --serve <addr>       Keep the tree in memory and serve fuzzy search over HTTP
+                       on <addr> (requires the server feature)
+  --verify             Recompute sizes from the leaves and fail on any mismatch
+  --fail-over <size>   List files larger than <size> and exit with status 1 if
+                       there are any
+  --assert-matches <expected.json>
+                       Compare the tree against a saved one and exit with
+                       status 1, listing the differences, if they differ
+  --explain-growth <old.json>
+                       List the directories whose size changed since a saved
+                       tree, largest change first
+  --record-run         Save the JSON tree with the fs-index version, root, time
+                       and options that produced it
+  --include <glob>     Keep only files matching <glob>, relative to the root
+                       (repeatable)
+  --ext <ext>[,<ext>...]
+                       Keep only files with one of these extensions, ignoring
+                       case (repeatable)
+  --exclude-regex <re> Skip entries whose path relative to the root matches
+                       <re>, before .gitignore rules apply (repeatable)
+  --exclude-vcs        Skip .git, .svn and .hg directories at any depth
+  --newer-than-file <path>
+                       Keep only files modified after <path> was
+  --not-accessed-since <age>
+                       Keep only files last accessed more than <age> ago, e.g.
+                       90d (units: s, m, h, d, w)
+  --hash               Store a BLAKE3 hash of each file in the tree
+  --dir-signatures     Store a signature of each directory's contents in the
+                       tree
+  --manifest <file>    Write a sorted path<TAB>size<TAB>mtime<TAB>hash manifest
+                       to <file>
+  --verify-manifest <file>
+                       Re-hash files and report changes against a manifest
+  --check-manifest <file>
+                       Without hashing, list files that changed size or only
+                       mtime since a manifest was written
+  --parallel-threshold <n>
+                       Index directories with fewer than <n> entries
+                       sequentially (default: 8)
+  --size-strategy <strategy>
+                       Stat files while walking with recursive (default), or
+                       afterwards in even chunks with flat
+  --size-chunk <n>     With --size-strategy flat, stat <n> files per task
+                       (default: 256)
+  --max-open-dirs <n>  List at most <n> directories at once, to stay within
+                       low open file limits (default: unlimited)
+  --io-retries <n>     Retry a directory listing or stat up to <n> times, with
+                       backoff, when it times out or is interrupted
+                       (default: 0)
+  --strict             Fail on the first entry that can't be read instead of
+                       skipping it with a warning
+  --ignored-size <policy>
+                       Size gitignored directories with sum-all (default) or
+                       apply-nested, which honors .gitignore files inside them
+  --progress percent   Count entries first, then show indexing progress as a
+                       percentage on stderr, and hashing progress by bytes
+  --no-parent-ignore   Don't apply .gitignore files above the folder, up to the
+                       enclosing repository root
+  --ignore-file <name> Read ignore rules from files named <name> instead of
+                       .gitignore; later names override earlier (repeatable)
+  --ignore-rules <rules>
+                       Also ignore what these newline-separated gitignore
+                       patterns match, as if at the root (repeatable)
+  --max-depth <n>      Store at most <n> levels below the folder; deeper
+                       directories are collapsed but still sized
+  --no-follow-symlinks Record symlinks as Symlink nodes instead of indexing
+                       their targets
+  --owner-only         Skip entries not owned by the current user (Unix only)
+  --physical-size      Size files by the blocks allocated to them, as on
+                       compressed or deduplicated filesystems (Unix only)
+  --names-only         Record only which entries exist, without file sizes or
+                       times, skipping a stat per file
+  --intern-names       Share the storage of repeated names, for less memory on
+                       trees with many files of the same name
+  --cache <file>       Reuse unchanged subtrees from <file> and save the new
+                       tree there
+  --block-overhead <size>
+                       Also report a weighted cost of size + file count * <size>";
+
+fn parse_args(args: impl IntoIterator<Item = String>) -> Option<Args> {
+    let mut args = args.into_iter().peekable();
+    let mut folder_path = None;
+    let mut load = None;
+    let mut merge_ndjson = Vec::new();
+    let mut top = DEFAULT_TOP;
+    let mut get = None;
+    let mut summary_only = false;
+    let mut fragmentation = false;
+    let mut type_summary = false;
+    let mut hotspots = None;
+    let mut wide_dirs = None;
+    let mut largest_dirs = None;
+    let mut exclude_ancestors = false;
+    let mut find_broken_symlinks = false;
+    let mut find_name_collisions = false;
+    let mut find_case_collisions = false;
+    let mut find_duplicate_dirs = false;
+    let mut find_duplicates = false;
+    let mut dedup_min_size = DEFAULT_DEDUP_MIN_SIZE;
+    let mut prune_empty = false;
+    let mut apply = false;
+    let mut find_empty_suspicious = Vec::new();
+    let mut search_query = "example".to_string();
+    let mut glob = None;
+    let mut glob_path = false;
+    let mut search_options = SearchOptions::default();
+    let mut print0 = false;
+    let mut repl = false;
+    let mut size_metric = SizeMetric::Bytes;
+    let mut path_style = PathStyle::default();
+    let mut base_path = None;
+    let mut git_root = false;
+    let mut verify = false;
+    let mut fail_over = None;
+    let mut assert_matches = None;
+    let mut explain_growth = None;
+    let mut record_run = false;
+    let mut tree_options = Vec::new();
+    let mut newer_than_file = None;
+    let mut not_accessed_since = None;
+    let mut hash = false;
+    let mut dir_signatures = false;
+    let mut manifest = None;
+    let mut verify_manifest = None;
+    let mut check_manifest = None;
+    let mut parallel_threshold = DEFAULT_PARALLEL_THRESHOLD;
+    let mut size_strategy = SizeStrategy::default();
+    let mut size_chunk = None;
+    let mut max_open_dirs = None;
+    let mut io_retries = 0;
+    let mut physical_size = false;
+    let mut strict = false;
+    let mut ignored_size = IgnoredSizePolicy::default();
+    let mut progress = false;
+    let mut parent_ignore = true;
+    let mut ignore_files = Vec::new();
+    let mut ignore_rules = Vec::new();
+    let mut max_depth = None;
+    let mut follow_symlinks = true;
+    let mut owner_only = false;
+    let mut names_only = false;
+    let mut intern_names = false;
+    let mut cache = None;
+    let mut format = Format::Json;
+    let mut json_compact = false;
+    let mut error_format = ErrorFormat::Text;
+    let mut no_config = false;
+    let mut flatmap_all = false;
+    let mut summary_record = false;
+    let mut output = None;
+    let mut output_dir_stats = None;
+    let mut redact = false;
+    let mut sort_size_desc = false;
+    let mut max_output_size = None;
+    let mut rollup_below = None;
+    let mut include = Vec::new();
+    let mut ext = Vec::new();
+    let mut exclude_regex = Vec::new();
+    #[cfg(feature = "server")]
+    let mut serve = None;
+
+    while let Some(arg) = args.next() {
+        if let Some((_, takes_value)) = TREE_OPTIONS.iter().find(|(flag, _)| *flag == arg) {
+            tree_options.push(arg.clone());
+            if *takes_value {
+                tree_options.extend(args.peek().cloned());
+            }
+        }
+        match arg.as_str() {
+            "--load" => load = Some(args.next()?),
+            "--merge-ndjson" => merge_ndjson.push(args.next()?),
+            "--top" => top = args.next()?.parse().ok()?,
+            "--get" => get = Some(args.next()?),
+            "--summary-only" => summary_only = true,
+            "--fragmentation" => fragmentation = true,
+            "--type-summary" => type_summary = true,
+            "--hotspots" => hotspots = Some(args.next()?.parse::<ByteSize>().ok()?.as_u64()),
+            "--wide-dirs" => wide_dirs = Some(args.next()?.parse().ok()?),
+            "--largest-dirs" => largest_dirs = Some(args.next()?.parse().ok()?),
+            "--exclude-ancestors" => exclude_ancestors = true,
+            "--find-broken-symlinks" => find_broken_symlinks = true,
+            "--find-name-collisions" => find_name_collisions = true,
+            "--find-case-collisions" => find_case_collisions = true,
+            "--find-duplicate-dirs" => find_duplicate_dirs = true,
+            "--find-duplicates" => find_duplicates = true,
+            "--dedup-min-size" => dedup_min_size = args.next()?.parse::<ByteSize>().ok()?.as_u64(),
+            "--prune-empty" => prune_empty = true,
+            "--apply" => apply = true,
+            "--find-empty-suspicious" => find_empty_suspicious.extend(
+                args.next()?
+                    .split(',')
+                    .map(|ext| ext.trim_start_matches('.').to_lowercase()),
+            ),
+            "--format" => {
+                format = match args.next()?.as_str() {
+                    "json" => Format::Json,
+                    "flatmap" => Format::FlatMap,
+                    "csv" => Format::Csv,
+                    "ndjson" => Format::Ndjson,
+                    "dot" => Format::Dot,
+                    "paths" => Format::Paths,
+                    "du" => Format::Du,
+                    #[cfg(feature = "parquet")]
+                    "parquet" => Format::Parquet,
+                    #[cfg(feature = "yaml")]
+                    "yaml" => Format::Yaml,
+                    #[cfg(feature = "protobuf")]
+                    "protobuf" => Format::Protobuf,
+                    _ => return None,
+                }
+            }
+            "--json-compact" => json_compact = true,
+            "--error-format" => {
+                error_format = match args.next()?.as_str() {
+                    "text" => ErrorFormat::Text,
+                    "json" => ErrorFormat::Json,
+                    _ => return None,
+                }
+            }
+            "--no-config" => no_config = true,
+            "--flatmap-all" => flatmap_all = true,
+            "--summary-record" => summary_record = true,
+            "--output" => output = Some(args.next()?),
+            "--output-dir-stats" => output_dir_stats = Some(args.next()?),
+            "--max-output-size" => {
+                max_output_size = Some(args.next()?.parse::<ByteSize>().ok()?.as_u64())
+            }
+            "--rollup-below" => {
+                rollup_below = Some(args.next()?.parse::<ByteSize>().ok()?.as_u64())
+            }
+            "--search" => search_query = args.next()?,
+            "--glob" => glob = Some(args.next()?),
+            "--glob-path" => glob_path = true,
+            "--ignore-case" => search_options.ignore_case = true,
+            "--normalize-unicode" => search_options.normalize_unicode = true,
+            "--resolve-symlinks" => search_options.resolve_symlinks = true,
+            "--print0" => print0 = true,
+            "--repl" => repl = true,
+            "--paths" => match args.next()?.as_str() {
+                "git-root" => git_root = true,
+                style => {
+                    path_style = style.parse().ok()?;
+                    git_root = false;
+                }
+            },
+            "--base-path" => base_path = Some(args.next()?),
+            "--verify" => verify = true,
+            "--fail-over" => fail_over = Some(args.next()?.parse::<ByteSize>().ok()?.as_u64()),
+            "--assert-matches" => assert_matches = Some(args.next()?),
+            "--explain-growth" => explain_growth = Some(args.next()?),
+            "--record-run" => record_run = true,
+            #[cfg(feature = "server")]
+            "--serve" => serve = Some(args.next()?),
+            "--redact" => redact = true,
+            "--sort" => {
+                if args.next()? != "size-desc" {
+                    return None;
+                }
+                sort_size_desc = true
+            }
+            "--include" => include.push(args.next()?),
+            "--ext" => ext.extend(
+                args.next()?
+                    .split(',')
+                    .map(|ext| ext.trim_start_matches('.').to_lowercase()),
+            ),
+            "--exclude-regex" => exclude_regex.push(args.next()?),
+            "--exclude-vcs" => exclude_regex.push(EXCLUDE_VCS.to_string()),
+            "--newer-than-file" => newer_than_file = Some(args.next()?),
+            "--not-accessed-since" => not_accessed_since = Some(parse_age(&args.next()?)?),
+            "--hash" => hash = true,
+            "--dir-signatures" => dir_signatures = true,
+            "--manifest" => manifest = Some(args.next()?),
+            "--verify-manifest" => verify_manifest = Some(args.next()?),
+            "--check-manifest" => check_manifest = Some(args.next()?),
+            "--parallel-threshold" => parallel_threshold = args.next()?.parse().ok()?,
+            "--size-strategy" => size_strategy = args.next()?.parse().ok()?,
+            "--size-chunk" => size_chunk = Some(args.next()?.parse().ok().filter(|&n| n > 0)?),
+            "--max-open-dirs" => {
+                max_open_dirs = Some(args.next()?.parse().ok().filter(|&limit| limit > 0)?)
+            }
+            "--io-retries" => io_retries = args.next()?.parse().ok()?,
+            "--physical-size" => physical_size = true,
+            "--strict" => strict = true,
+            "--ignored-size" => ignored_size = args.next()?.parse().ok()?,
+            "--progress=percent" => progress = true,
+            "--progress" => {
+                if args.next()? != "percent" {
+                    return None;
+                }
+                progress = true
+            }
+            "--no-parent-ignore" => parent_ignore = false,
+            "--ignore-file" => ignore_files.push(args.next()?),
+            "--ignore-rules" => ignore_rules.push(args.next()?),
+            "--max-depth" => max_depth = Some(args.next()?.parse().ok()?),
+            "--no-follow-symlinks" => follow_symlinks = false,
+            "--owner-only" => owner_only = true,
+            "--names-only" => names_only = true,
+            "--intern-names" => intern_names = true,
+            "--cache" => cache = Some(args.next()?),
+            "--block-overhead" => {
+                let block_overhead = args.next()?.parse::<ByteSize>().ok()?.as_u64();
+                size_metric = SizeMetric::Weighted { block_overhead };
+            }
+            _ if folder_path.is_none() && !arg.starts_with("--") => folder_path = Some(arg),
+            _ => return None,
+        }
+    }
+
+    // Exactly one source of entries.
+    let sources = [
+        folder_path.is_some(),
+        load.is_some(),
+        !merge_ndjson.is_empty(),
+    ];
+    if sources.into_iter().filter(|&given| given).count() != 1 {
+        return None;
+    }
+    // Deleting is only ever done on purpose.
+    if apply && !prune_empty {
+        return None;
     }
-    Ok(builder.build().unwrap())
+    // A loaded tree's root is only known by its name, or by a path saved
+    // elsewhere, so the files they read or delete needn't be the ones listed.
+    if load.is_some() && (prune_empty || find_duplicates) {
+        return None;
+    }
+    // The repository root is only a base found on disk.
+    if git_root {
+        if base_path.is_some() {
+            return None;
+        }
+        path_style = PathStyle::Absolute;
+    }
+    // Paths from a base are the absolute ones with the base left out.
+    if base_path.is_some() {
+        if path_style == PathStyle::Name {
+            return None;
+        }
+        path_style = PathStyle::Absolute;
+    }
+    // A physical path has no name-only form to fall back to.
+    if search_options.resolve_symlinks && path_style == PathStyle::Name {
+        return None;
+    }
+    // Without a tree there is nothing to load or filter.
+    if summary_only
+        && (load.is_some()
+            || !include.is_empty()
+            || !ext.is_empty()
+            || newer_than_file.is_some()
+            || not_accessed_since.is_some())
+    {
+        return None;
+    }
+    // Without file sizes and times, whatever depends on them would report
+    // nonsense, and --prune-empty would take every file for an empty one.
+    // Cached subtrees would bring sizes back in.
+    if names_only
+        && (hash
+            || fail_over.is_some()
+            || hotspots.is_some()
+            || largest_dirs.is_some()
+            || fragmentation
+            || find_duplicates
+            || find_duplicate_dirs
+            || prune_empty
+            || !find_empty_suspicious.is_empty()
+            || explain_growth.is_some()
+            || newer_than_file.is_some()
+            || not_accessed_since.is_some()
+            || max_output_size.is_some()
+            || rollup_below.is_some()
+            || cache.is_some())
+    {
+        return None;
+    }
+    // Sizes come from the saved tree, and files with the same contents can
+    // take different space on disk, or none at all when stored inline.
+    if physical_size
+        && (load.is_some()
+            || names_only
+            || find_duplicates
+            || prune_empty
+            || !find_empty_suspicious.is_empty())
+    {
+        return None;
+    }
+    if size_chunk.is_some() && size_strategy != SizeStrategy::Flat {
+        return None;
+    }
+    if exclude_ancestors && largest_dirs.is_none() {
+        return None;
+    }
+    if glob_path && glob.is_none() {
+        return None;
+    }
+    // Only a tree freshly indexed into JSON has a run to record.
+    if record_run && (load.is_some() || !matches!(format, Format::Json)) {
+        return None;
+    }
+    // Cached subtrees keep the access times of the run that saved them, and
+    // reading a file doesn't change its directory's mtime to invalidate them.
+    if not_accessed_since.is_some() && cache.is_some() {
+        return None;
+    }
+
+    Some(Args {
+        folder_path,
+        load,
+        merge_ndjson,
+        top,
+        get,
+        summary_only,
+        fragmentation,
+        type_summary,
+        hotspots,
+        wide_dirs,
+        largest_dirs,
+        exclude_ancestors,
+        find_broken_symlinks,
+        find_name_collisions,
+        find_case_collisions,
+        find_duplicate_dirs,
+        find_duplicates,
+        dedup_min_size,
+        prune_empty,
+        apply,
+        find_empty_suspicious,
+        search_query,
+        glob,
+        glob_path,
+        search_options,
+        print0,
+        repl,
+        size_metric,
+        path_style,
+        base_path,
+        git_root,
+        verify,
+        fail_over,
+        assert_matches,
+        explain_growth,
+        record_run,
+        tree_options,
+        newer_than_file,
+        not_accessed_since,
+        hash,
+        dir_signatures,
+        manifest,
+        verify_manifest,
+        check_manifest,
+        parallel_threshold,
+        size_strategy,
+        size_chunk,
+        max_open_dirs,
+        io_retries,
+        physical_size,
+        strict,
+        ignored_size,
+        progress,
+        parent_ignore,
+        ignore_files,
+        ignore_rules,
+        max_depth,
+        follow_symlinks,
+        owner_only,
+        names_only,
+        intern_names,
+        cache,
+        format,
+        json_compact,
+        error_format,
+        no_config,
+        flatmap_all,
+        summary_record,
+        output,
+        output_dir_stats,
+        redact,
+        sort_size_desc,
+        max_output_size,
+        rollup_below,
+        include,
+        ext,
+        exclude_regex,
+        #[cfg(feature = "server")]
+        serve,
+    })
 }
 
-fn calculate_ignored_size(path: &Path) -> io::Result<u64> {
-    let mut total_size = 0;
-    for entry in fs::read_dir(path)? {
-        let entry = entry?;
-        let metadata = entry.metadata()?;
-        if metadata.is_file() {
-            total_size += metadata.len();
-        } else if metadata.is_dir() {
-            total_size += calculate_ignored_size(&entry.path())?;
+/// Name of the optional config file, looked for in the folder being indexed
+/// and then in the working directory.
+const CONFIG_FILE: &str = ".fsindex.toml";
+
+/// Options read from [`CONFIG_FILE`], each standing for the command-line flag
+/// of the same name.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct ConfigFile {
+    format: Option<String>,
+    json_compact: Option<bool>,
+    output: Option<String>,
+    paths: Option<String>,
+    #[serde(default)]
+    include: Vec<String>,
+    #[serde(default)]
+    ext: Vec<String>,
+    #[serde(default)]
+    ignore_file: Vec<String>,
+    #[serde(default)]
+    exclude_regex: Vec<String>,
+    exclude_vcs: Option<bool>,
+    newer_than_file: Option<String>,
+    max_depth: Option<usize>,
+    ignored_size: Option<String>,
+    parent_ignore: Option<bool>,
+    follow_symlinks: Option<bool>,
+    parallel_threshold: Option<usize>,
+    max_open_dirs: Option<usize>,
+    io_retries: Option<u32>,
+    physical_size: Option<bool>,
+    strict: Option<bool>,
+    normalize_unicode: Option<bool>,
+    ignore_case: Option<bool>,
+    hash: Option<bool>,
+    cache: Option<String>,
+}
+
+impl ConfigFile {
+    fn load(path: &Path) -> io::Result<ConfigFile> {
+        toml::from_str(&fs::read_to_string(path)?).map_err(|err| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid {}: {}", path.display(), err),
+            )
+        })
+    }
+
+    /// The flags this file stands for, to be parsed ahead of the command line
+    /// so that explicit flags win.
+    fn to_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        let mut value = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(flag.to_string());
+                args.push(value);
+            }
+        };
+        value("--format", self.format.clone());
+        value("--output", self.output.clone());
+        value("--paths", self.paths.clone());
+        value("--newer-than-file", self.newer_than_file.clone());
+        value("--max-depth", self.max_depth.map(|depth| depth.to_string()));
+        value("--ignored-size", self.ignored_size.clone());
+        value(
+            "--parallel-threshold",
+            self.parallel_threshold
+                .map(|threshold| threshold.to_string()),
+        );
+        value(
+            "--max-open-dirs",
+            self.max_open_dirs.map(|limit| limit.to_string()),
+        );
+        value(
+            "--io-retries",
+            self.io_retries.map(|retries| retries.to_string()),
+        );
+        value("--cache", self.cache.clone());
+        for pattern in &self.include {
+            value("--include", Some(pattern.clone()));
+        }
+        for name in &self.ignore_file {
+            value("--ignore-file", Some(name.clone()));
+        }
+        for ext in &self.ext {
+            value("--ext", Some(ext.clone()));
+        }
+        for pattern in &self.exclude_regex {
+            value("--exclude-regex", Some(pattern.clone()));
         }
+
+        let flags = [
+            ("--json-compact", self.json_compact == Some(true)),
+            ("--exclude-vcs", self.exclude_vcs == Some(true)),
+            ("--no-parent-ignore", self.parent_ignore == Some(false)),
+            ("--no-follow-symlinks", self.follow_symlinks == Some(false)),
+            ("--physical-size", self.physical_size == Some(true)),
+            ("--strict", self.strict == Some(true)),
+            ("--normalize-unicode", self.normalize_unicode == Some(true)),
+            ("--ignore-case", self.ignore_case == Some(true)),
+            ("--hash", self.hash == Some(true)),
+        ];
+        args.extend(
+            flags
+                .into_iter()
+                .filter(|(_, set)| *set)
+                .map(|(flag, _)| flag.to_string()),
+        );
+        args
+    }
+}
+
+/// The config file that applies to `folder_path`: the one inside it, or
+/// else the one in the working directory.
+fn find_config_file(folder_path: Option<&str>) -> Option<PathBuf> {
+    folder_path
+        .map(|folder| Path::new(folder).join(CONFIG_FILE))
+        .into_iter()
+        .chain([PathBuf::from(CONFIG_FILE)])
+        .find(|path| path.is_file())
+}
+
+/// Parses `cli` again after the options of the config file found for `args`,
+/// so that the file's values fill in whatever the command line doesn't set.
+fn with_config_file(args: Args, cli: Vec<String>) -> io::Result<Args> {
+    if args.no_config {
+        return Ok(args);
+    }
+    let Some(path) = find_config_file(args.folder_path.as_deref()) else {
+        return Ok(args);
+    };
+    debug!("reading options from {}", path.display());
+    let config = ConfigFile::load(&path)?;
+    parse_args(config.to_args().into_iter().chain(cli)).ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid option value in {}", path.display()),
+        )
+    })
+}
+
+/// The `--output` that stands for stdout.
+const STDOUT: &str = "-";
+
+/// Whether the output goes to stdout rather than a file.
+fn writes_stdout(args: &Args) -> bool {
+    args.output.as_deref() == Some(STDOUT)
+}
+
+/// Prints a human-readable status line. With `--print0` or `--output -`
+/// stdout is reserved for paths or the output, so status lines go to stderr
+/// instead.
+fn status(args: &Args, message: &str) {
+    if args.print0 || writes_stdout(args) {
+        eprintln!("{}", message);
+    } else {
+        println!("{}", message);
+    }
+}
+
+/// Counts the bytes written to it, to measure serialized size without
+/// buffering the output.
+#[derive(Default)]
+struct CountingWriter(u64);
+
+impl Write for CountingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn json_size(root: &FileNode, compact: bool) -> u64 {
+    let mut counter = CountingWriter::default();
+    Json { compact }
+        .write(root, &mut counter)
+        .expect("FileNode serializes to JSON");
+    counter.0
+}
+
+/// The [`OutputFormat`] selected by `--format` and the options that go with it.
+fn output_format(args: &Args, root_path: &Path) -> Box<dyn OutputFormat> {
+    let paths = FlatPaths {
+        root_path: root_path.to_path_buf(),
+        style: args.path_style,
+    };
+    let json = Json {
+        compact: args.json_compact,
+    };
+    match args.format {
+        Format::Json => Box::new(json),
+        Format::FlatMap => Box::new(FlatMap {
+            paths,
+            all_nodes: args.flatmap_all,
+            json,
+        }),
+        Format::Csv => Box::new(Csv {
+            paths,
+            summary: args.summary_record,
+        }),
+        Format::Ndjson => Box::new(Ndjson {
+            paths,
+            summary: args.summary_record,
+        }),
+        Format::Dot => Box::new(Dot),
+        Format::Paths => Box::new(Paths {
+            paths,
+            nul: args.print0,
+        }),
+        Format::Du => Box::new(Du { paths }),
+        #[cfg(feature = "parquet")]
+        Format::Parquet => Box::new(fs_index::output::Parquet { paths }),
+        #[cfg(feature = "yaml")]
+        Format::Yaml => Box::new(fs_index::output::Yaml),
+        #[cfg(feature = "protobuf")]
+        Format::Protobuf => Box::new(fs_index::output::Protobuf),
     }
-    Ok(total_size)
 }
 
-fn index_folder(path: &Path, gitignore: &Gitignore) -> io::Result<FileNode> {
-    let metadata = fs::metadata(path)?;
-    let name = path.file_name().unwrap().to_string_lossy().into_owned();
+/// Counts the bytes `inner` accepted, to tell how far a failed write got.
+struct TrackedWriter<W> {
+    inner: W,
+    written: u64,
+}
+
+impl<W: Write> Write for TrackedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
 
-    if metadata.is_file() {
-        if gitignore.matched(path, false).is_ignore() {
-            return Ok(FileNode::new(name, metadata.len(), NodeType::File));
+/// Writes `root` to `output` as it is serialized. On failure, e.g. a full
+/// disk, the partial file is left in place and the error says how many bytes
+/// made it there.
+fn write_output(args: &Args, root: &FileNode, root_path: &Path, output: &str) -> io::Result<()> {
+    let inner: Box<dyn Write> = if output == STDOUT {
+        Box::new(io::stdout().lock())
+    } else {
+        Box::new(File::create(output)?)
+    };
+    let mut writer = BufWriter::new(TrackedWriter { inner, written: 0 });
+    let written = if args.record_run {
+        let snapshot = Snapshot {
+            metadata: RunMetadata::new(root_path.to_path_buf(), args.tree_options.clone()),
+            tree: root,
+        };
+        Json {
+            compact: args.json_compact,
         }
-        Ok(FileNode::new(name, metadata.len(), NodeType::File))
+        .write_value(&snapshot, &mut writer)
     } else {
-        let mut node = FileNode::new(name, 0, NodeType::Directory);
-        let new_gitignore = read_gitignore(path)?;
-
-        if new_gitignore.matched(path, true).is_ignore() {
-            let size = calculate_ignored_size(path)?;
-            return Ok(FileNode::new(
-                path.to_string_lossy().into_owned(),
-                size,
-                NodeType::IgnoredDirectory,
-            ));
-        }
-
-        let children: Vec<FileNode> = fs::read_dir(path)?
-            .par_bridge()
-            .filter_map(|entry| {
-                entry
-                    .ok()
-                    .and_then(|e| index_folder(&e.path(), &new_gitignore).ok())
-            })
-            .collect();
+        output_format(args, root_path).write(root, &mut writer)
+    };
+    let Err(err) = written else {
+        return Ok(());
+    };
+    // The error may have been transient, so what is still buffered gets one
+    // more chance to reach the file.
+    let _ = writer.flush();
+    Err(io::Error::new(
+        err.kind(),
+        format!(
+            "writing {} failed after {} bytes, leaving it incomplete: {}",
+            output,
+            writer.get_ref().written,
+            err
+        ),
+    ))
+}
 
-        for child in children {
-            node.add_child(child);
+/// Prints the combined totals of the `--merge-ndjson` exports, streaming each
+/// instead of building a tree.
+fn merge_ndjson(args: &Args) -> io::Result<()> {
+    let mut aggregate = Aggregate::new(args.top);
+    for path in &args.merge_ndjson {
+        let reader = BufReader::new(File::open(path)?);
+        aggregate.add_ndjson(path, reader)?;
+    }
+    println!(
+        "Merged {} exports: {} in {} files",
+        args.merge_ndjson.len(),
+        ByteSize::b(aggregate.total_size),
+        aggregate.file_count
+    );
+    println!("By extension:");
+    for (ext, total) in aggregate.extensions() {
+        let ext = if ext.is_empty() { "(none)" } else { ext };
+        println!("{}	{}	{}", ByteSize::b(total.size), total.files, ext);
+    }
+    println!("Largest files:");
+    for file in aggregate.largest() {
+        println!("{}	{}	{}", ByteSize::b(file.size), file.source, file.path);
+    }
+    Ok(())
+}
+
+/// Loads the tree at `path` to compare against, warning if it was recorded
+/// with `--record-run` and indexed with other [`TREE_OPTIONS`] than this run,
+/// since differences may then come from the options rather than the disk.
+fn load_to_compare(args: &Args, path: &str) -> io::Result<FileNode> {
+    let (tree, metadata) = load_snapshot(Path::new(path))?;
+    if let Some(metadata) = metadata {
+        if args.load.is_none() && metadata.options != args.tree_options {
+            eprintln!(
+                "Warning: {} was indexed with options [{}], this run with [{}]",
+                path,
+                metadata.options.join(" "),
+                args.tree_options.join(" ")
+            );
         }
+    }
+    Ok(tree)
+}
 
-        Ok(node)
+/// Writes the size of each directory directly under the root to
+/// `<dir>/<name>.size`, for shell-based monitoring. Names that would collide,
+/// including on case-insensitive filesystems, get a `-2`, `-3`, ... suffix.
+/// Returns the number of files written.
+fn write_dir_stats(root: &FileNode, dir: &Path) -> io::Result<usize> {
+    fs::create_dir_all(dir)?;
+    let mut taken = HashSet::new();
+    let mut written = 0;
+    for child in &root.children {
+        if matches!(
+            child.node_type,
+            NodeType::File | NodeType::Special | NodeType::Symlink { .. }
+        ) {
+            continue;
+        }
+        let mut file_name = format!("{}.size", child.name);
+        let mut suffix = 1;
+        while !taken.insert(file_name.to_lowercase()) {
+            suffix += 1;
+            file_name = format!("{}-{}.size", child.name, suffix);
+        }
+        fs::write(dir.join(&file_name), format!("{}\n", child.size))?;
+        written += 1;
     }
+    Ok(written)
 }
 
-fn fuzzy_search(root: &FileNode, query: &str) -> Vec<String> {
-    let matcher = SkimMatcherV2::default();
-    let mut results = Vec::new();
+/// Formats a change in bytes with its sign, e.g. `+1.2 MiB`.
+fn signed_size(change: i128) -> String {
+    let sign = if change < 0 { '-' } else { '+' };
+    format!("{}{}", sign, ByteSize::b(change.unsigned_abs() as u64))
+}
+
+/// Prints the size of `node` and of its immediate children.
+fn print_node(path: &str, node: &FileNode) {
+    println!("{}\t{}", path, ByteSize::b(node.size));
+    for child in &node.children {
+        println!("  {}\t{}", child.name, ByteSize::b(child.size));
+    }
+}
+
+/// Answers queries read from stdin, one per line, until EOF. A line is a
+/// fuzzy query unless it is one of the commands `:largest <n>`, listing the
+/// `n` largest files, or `:path <path>`, which works like `--get`.
+fn repl(args: &Args, root: &FileNode, root_path: &Path) -> io::Result<()> {
+    let searcher = Searcher::new(&args.search_options);
+    let interactive = io::stdin().is_terminal();
+    let mut lines = io::stdin().lock().lines();
+    loop {
+        if interactive {
+            eprint!("> ");
+        }
+        let Some(line) = lines.next() else {
+            return Ok(());
+        };
+        let line = line?;
+        let line = line.trim();
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+        let argument = argument.trim();
+        match command {
+            "" => {}
+            ":largest" => {
+                let Ok(count) = argument.parse::<usize>() else {
+                    eprintln!("Usage: :largest <n>");
+                    continue;
+                };
+                let mut files: Vec<_> = root.files().collect();
+                files.sort_by_key(|(_, file)| std::cmp::Reverse(file.size));
+                for (path, file) in files.iter().take(count) {
+                    println!(
+                        "{}\t{}",
+                        ByteSize::b(file.size),
+                        args.path_style.render(root_path, path)
+                    );
+                }
+            }
+            ":path" => match root.get(argument) {
+                Some(node) => print_node(argument, node),
+                None => eprintln!("No node at {}", argument),
+            },
+            _ if command.starts_with(':') => eprintln!("Unknown command {}", command),
+            _ => {
+                let mut matches = Vec::new();
+                searcher.each(root, line, |path, score| matches.push((path, score)));
+                matches.sort_by_key(|(_, score)| std::cmp::Reverse(*score));
+                for (path, _) in &matches {
+                    println!(
+                        "{}",
+                        args.search_options
+                            .render(root, args.path_style, root_path, path)
+                    );
+                }
+            }
+        }
+    }
+}
 
-    fn search_recursive(
-        node: &FileNode,
-        query: &str,
-        matcher: &SkimMatcherV2,
-        results: &mut Vec<String>,
-        path: &mut Vec<String>,
-    ) {
-        if matcher.fuzzy_match(&node.name, query).is_some() {
-            results.push(path.join("/"));
+/// Writes the path of each match terminated by `\0`, suitable for
+/// `xargs -0`, as soon as it is found.
+fn print_matches0(args: &Args, root: &FileNode, root_path: &Path) -> io::Result<()> {
+    let mut stdout = io::stdout().lock();
+    let mut result = Ok(());
+    fuzzy_search_each(root, &args.search_query, &args.search_options, |path, _| {
+        if result.is_ok() {
+            let path = args
+                .search_options
+                .render(root, args.path_style, root_path, &path);
+            result = stdout
+                .write_all(path.as_bytes())
+                .and_then(|()| stdout.write_all(b"\0"));
         }
+    });
+    result?;
+    stdout.flush()
+}
 
-        path.push(node.name.clone());
-        for child in &node.children {
-            search_recursive(child, query, matcher, results, path);
+/// Redraws a percentage and the time left on stderr until `finished` is set.
+/// `bytes` says whether `progress` counts bytes rather than entries.
+fn report_progress(progress: &Progress, finished: &AtomicBool, label: &str, bytes: bool) {
+    let amount = |count: u64| {
+        if bytes {
+            ByteSize::b(count).to_string()
+        } else {
+            count.to_string()
         }
-        path.pop();
+    };
+    let unit = if bytes { "" } else { " entries" };
+    while !finished.load(Ordering::Relaxed) {
+        let eta = progress
+            .eta()
+            .map(|eta| format!(", {:.0?} left", eta))
+            .unwrap_or_default();
+        eprint!(
+            "\r{}: {}% ({}/{}{}{})    ",
+            label,
+            progress.percent(),
+            amount(progress.done()),
+            amount(progress.total()),
+            unit,
+            eta
+        );
+        thread::sleep(Duration::from_millis(100));
     }
+    eprintln!(
+        "\r{}: 100% ({}{})                    ",
+        label,
+        amount(progress.done()),
+        unit
+    );
+}
 
-    let mut path = Vec::new();
-    search_recursive(root, query, &matcher, &mut results, &mut path);
-    results
+/// A failure as printed by `--error-format json`.
+#[derive(Serialize)]
+struct ErrorReport<'a> {
+    /// The `io::ErrorKind`, e.g. `NotFound` or `PermissionDenied`.
+    error: String,
+    /// The folder or tree file being read, for failures while loading or
+    /// indexing.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<&'a str>,
+    message: String,
 }
 
-fn main() -> io::Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        println!("Usage: {} <folder_path>", args[0]);
-        return Ok(());
+/// Prints `err` as a single-line JSON [`ErrorReport`] on stderr and exits
+/// with `code`.
+fn exit_with_json_error(err: &io::Error, path: Option<&str>, code: i32) -> ! {
+    let report = ErrorReport {
+        error: format!("{:?}", err.kind()),
+        path,
+        message: err.to_string(),
+    };
+    // Nothing more can be reported if stderr itself fails.
+    if let Ok(line) = serde_json::to_string(&report) {
+        eprintln!("{}", line);
+    }
+    std::process::exit(code);
+}
+
+/// The effective user id, which `--owner-only` keeps entries of. There is
+/// no such id outside Unix, where the flag does nothing.
+fn current_uid() -> Option<u32> {
+    #[cfg(unix)]
+    {
+        // SAFETY: geteuid has no preconditions and always succeeds.
+        Some(unsafe { libc::geteuid() })
     }
+    #[cfg(not(unix))]
+    {
+        None
+    }
+}
 
-    let folder_path = &args[1];
-    let root_gitignore = read_gitignore(Path::new(folder_path))?;
+/// Exit status when the folder to index can't be read at all, as in
+/// sysexits' `EX_NOPERM`.
+const EXIT_ROOT_PERMISSION_DENIED: i32 = 77;
 
+/// Whether indexing failed because the root folder itself is unreadable,
+/// rather than e.g. the cache file. Unreadable subdirectories are skipped
+/// while walking and never fail the run.
+fn root_unreadable(args: &Args) -> bool {
+    let (None, Some(folder)) = (&args.load, &args.folder_path) else {
+        return false;
+    };
+    matches!(fs::read_dir(folder), Err(err) if err.kind() == io::ErrorKind::PermissionDenied)
+}
+
+/// Loads or indexes the tree and applies the filters and transforms that
+/// shape its content. Returns the tree, the path it was indexed from and how
+/// long loading or indexing took.
+fn build_tree(args: &Args) -> io::Result<(FileNode, PathBuf, Duration)> {
     let start = Instant::now();
-    let root = index_folder(Path::new(folder_path), &root_gitignore)?;
+    let (mut root, mut root_path) = match (&args.load, &args.folder_path) {
+        (Some(load), _) => {
+            let root: FileNode = load_tree(Path::new(load))?;
+            // Absolute renderings need the original root, which is only known
+            // if the tree was saved with absolute paths.
+            let root_path = match &root.path {
+                Some(path) if Path::new(path).is_absolute() => PathBuf::from(path),
+                _ => PathBuf::from(&root.name),
+            };
+            (root, root_path)
+        }
+        (None, Some(folder_path)) => {
+            let root_path = fs::canonicalize(folder_path)?;
+            let normalize_unicode = args.search_options.normalize_unicode;
+            // A childless summary must neither use nor become the cache.
+            let cache_path = args.cache.as_deref().filter(|_| !args.summary_only);
+            let cache = cache_path.and_then(|c| TreeCache::load(Path::new(c)));
+            let mut config = IndexConfig::new()
+                .normalize_unicode(normalize_unicode)
+                .parallel_threshold(args.parallel_threshold)
+                .ignored_size(args.ignored_size)
+                .parent_ignore(args.parent_ignore)
+                .follow_symlinks(args.follow_symlinks)
+                .names_only(args.names_only)
+                .io_retries(args.io_retries)
+                .physical_size(args.physical_size)
+                .strict(args.strict)
+                .intern_names(args.intern_names)
+                .size_strategy(args.size_strategy)
+                .exclude(RegexSet::new(&args.exclude_regex).map_err(io::Error::other)?);
+            if let Some(depth) = args.max_depth {
+                config = config.max_depth(depth);
+            }
+            if let Some(limit) = args.max_open_dirs {
+                config = config.max_open_dirs(limit);
+            }
+            if let Some(chunk) = args.size_chunk {
+                config = config.size_chunk(chunk);
+            }
+            let ignore_files = if args.ignore_files.is_empty() {
+                vec![DEFAULT_IGNORE_FILE.to_string()]
+            } else {
+                args.ignore_files.clone()
+            };
+            config = config.ignore_files(ignore_files.clone());
+            for rules in &args.ignore_rules {
+                config = config.ignore_rules(rules);
+            }
+            let ignore_rules: Vec<String> = args
+                .ignore_rules
+                .iter()
+                .flat_map(|rules| rules.lines().map(str::to_string))
+                .collect();
+            let owner = args.owner_only.then(current_uid).flatten();
+            #[cfg(unix)]
+            if let Some(uid) = owner {
+                config = config.owner(uid);
+            }
+            let key = CacheKey {
+                root: root_path.clone(),
+                normalize_unicode,
+                exclude: args.exclude_regex.clone(),
+                owner,
+                ignore_files,
+                ignore_rules,
+                physical_size: args.physical_size,
+                max_depth: args.max_depth,
+                follow_symlinks: args.follow_symlinks,
+                parent_ignore: args.parent_ignore,
+            };
+            let progress = args
+                .progress
+                .then(|| Progress::new(count_entries(&root_path)));
+            let indexed = AtomicBool::new(false);
+            let root = thread::scope(|scope| {
+                if let Some(progress) = &progress {
+                    scope.spawn(|| report_progress(progress, &indexed, "Indexing", false));
+                }
+                let root = if args.summary_only {
+                    config.summarize(&root_path, progress.as_ref())
+                } else {
+                    config.index_with(
+                        &root_path,
+                        cache.as_ref().and_then(|cache| cache.tree_for(&key)),
+                        progress.as_ref(),
+                    )
+                };
+                indexed.store(true, Ordering::Relaxed);
+                root
+            })?;
+            if let Some(cache_path) = cache_path {
+                // Saved before any filtering, so the next run sees the whole tree.
+                let cache = TreeCache {
+                    key,
+                    tree: root.clone(),
+                };
+                cache.save(Path::new(cache_path))?;
+            }
+            (root, root_path)
+        }
+        (None, None) => unreachable!("parse_args requires a folder or --load"),
+    };
     let duration = start.elapsed();
-    // Serialize the tree
-    let serialized = serde_json::to_string_pretty(&root)?;
-    fs::write("file_tree.json", serialized)?;
-    // println!("{:#?}", root);
-    println!("File tree has been indexed and saved to file_tree.json");
-    println!("Time taken to index: {:?}", duration);
-    println!("Total size: {}", ByteSize::b(root.size));
+    if !args.include.is_empty() {
+        let include = build_globset(&args.include).map_err(io::Error::other)?;
+        root.retain_files(&|path, _| include.is_match(path));
+    }
+    if !args.ext.is_empty() {
+        root.retain_files(&|_, file| {
+            Path::new(&file.name)
+                .extension()
+                .is_some_and(|ext| args.ext.contains(&ext.to_string_lossy().to_lowercase()))
+        });
+    }
+    if let Some(reference) = &args.newer_than_file {
+        let reference = fs::metadata(reference)?.modified()?;
+        root.retain_files(&|_, file| file.mtime.is_some_and(|mtime| mtime > reference));
+    }
+    if let Some(age) = args.not_accessed_since {
+        if atimes_track_mtimes(&root) {
+            eprintln!(
+                "Warning: every file was last accessed when it was last modified; the \
+                 filesystem may be mounted with noatime, making --not-accessed-since unreliable"
+            );
+        }
+        let cutoff = SystemTime::now() - age;
+        root.retain_files(&|_, file| file.atime.is_some_and(|atime| atime < cutoff));
+    }
+    // A loaded tree keeps the hashes it was saved with.
+    let needs_hashes = args.hash || args.manifest.is_some() || args.verify_manifest.is_some();
+    if needs_hashes && args.load.is_none() {
+        if args.progress {
+            // By bytes, since reading dominates and file sizes vary widely.
+            let progress = Progress::new(root.file_bytes());
+            let hashed = AtomicBool::new(false);
+            thread::scope(|scope| {
+                scope.spawn(|| report_progress(&progress, &hashed, "Hashing", true));
+                root.compute_hashes_with(&root_path, Some(&progress));
+                hashed.store(true, Ordering::Relaxed);
+            });
+        } else {
+            root.compute_hashes(&root_path);
+        }
+    }
+    // Before redacting, which would give identical directories different
+    // placeholder names.
+    if args.dir_signatures || args.find_duplicate_dirs {
+        root.compute_signatures();
+    }
+    if args.sort_size_desc {
+        root.sort_by_size_desc();
+    }
+    if args.redact {
+        root.redact_names();
+        // Absolute paths would otherwise leak the real location of the root.
+        root_path = PathBuf::from(&root.name);
+    }
+    root.assign_paths(&display_root(args, &root_path)?, args.path_style);
+    Ok((root, root_path, duration))
+}
+
+/// Turns `--paths git-root` into the `--base-path` of the repository the
+/// folder is in, or back into root-relative paths when the folder is the
+/// repository root itself, is in no repository, or a tree is loaded
+/// instead.
+fn resolve_git_root(args: &mut Args) -> io::Result<()> {
+    if !args.git_root {
+        return Ok(());
+    }
+    args.git_root = false;
+    args.path_style = PathStyle::Relative;
+    let Some(folder) = &args.folder_path else {
+        eprintln!("Warning: --paths git-root needs a folder to index, using relative paths");
+        return Ok(());
+    };
+    let folder = fs::canonicalize(folder)?;
+    match find_repo_root(&folder) {
+        Some(repo) if repo != folder => {
+            debug!("rendering paths relative to {}", repo.display());
+            args.base_path = Some(repo.to_string_lossy().into_owned());
+            args.path_style = PathStyle::Absolute;
+        }
+        Some(_) => {}
+        None => eprintln!(
+            "Warning: {} is not inside a git repository, using paths relative to it",
+            folder.display()
+        ),
+    }
+    Ok(())
+}
+
+/// The root that paths are rendered from: the indexed root itself, or with
+/// `--base-path` the part of it below the base.
+fn display_root(args: &Args, root_path: &Path) -> io::Result<PathBuf> {
+    match &args.base_path {
+        Some(base) => {
+            let base = fs::canonicalize(base).unwrap_or_else(|_| PathBuf::from(base));
+            strip_base(root_path, &base)
+        }
+        None => Ok(root_path.to_path_buf()),
+    }
+}
+
+#[cfg(feature = "server")]
+fn serve(args: Args, root: FileNode, root_path: PathBuf, addr: &str) -> io::Result<()> {
+    use fs_index::server::ServeOptions;
+
+    let options = ServeOptions {
+        search_options: args.search_options.clone(),
+        path_style: args.path_style,
+        root_path,
+    };
+    status(&args, &format!("Serving search on http://{}", addr));
+    let reindex = Box::new(move || build_tree(&args).map(|(root, _, _)| root));
+    fs_index::server::serve(addr, root, options, reindex)
+}
+
+fn main() -> io::Result<()> {
+    env_logger::init();
+
+    let cli: Vec<String> = std::env::args().skip(1).collect();
+    let Some(args) = parse_args(cli.clone()) else {
+        let program = std::env::args().next().unwrap_or_default();
+        println!(
+            "Usage: {} <folder_path> [options]\n       {} --load <file> [options]\n       \
+             {} --merge-ndjson <file> [--merge-ndjson <file>...] [--top <n>]\n\n{}",
+            program, program, program, OPTIONS
+        );
+        return Ok(());
+    };
+
+    let error_format = args.error_format;
+    if !args.merge_ndjson.is_empty() {
+        return match merge_ndjson(&args) {
+            Err(err) if error_format == ErrorFormat::Json => exit_with_json_error(&err, None, 1),
+            result => result,
+        };
+    }
+    let args = match with_config_file(args, cli).and_then(|mut args| {
+        resolve_git_root(&mut args)?;
+        Ok(args)
+    }) {
+        Ok(args) => args,
+        Err(err) if error_format == ErrorFormat::Json => exit_with_json_error(&err, None, 1),
+        Err(err) => return Err(err),
+    };
+    let (root, root_path, duration) = match build_tree(&args) {
+        Ok(tree) => tree,
+        Err(err) if err.kind() == io::ErrorKind::PermissionDenied && root_unreadable(&args) => {
+            let folder = args.folder_path.as_deref().unwrap_or_default();
+            if error_format == ErrorFormat::Json {
+                exit_with_json_error(&err, Some(folder), EXIT_ROOT_PERMISSION_DENIED);
+            }
+            eprintln!("Error: permission denied reading {}: {}", folder, err);
+            eprintln!(
+                "The folder itself can't be read. Run with elevated permissions (e.g. sudo) \
+                 or pick a folder you can read."
+            );
+            std::process::exit(EXIT_ROOT_PERMISSION_DENIED);
+        }
+        Err(err) if error_format == ErrorFormat::Json => {
+            let source = args.load.as_deref().or(args.folder_path.as_deref());
+            exit_with_json_error(&err, source, 1);
+        }
+        Err(err) => return Err(err),
+    };
+    match run(args, root, root_path, duration) {
+        Err(err) if error_format == ErrorFormat::Json => exit_with_json_error(&err, None, 1),
+        result => result,
+    }
+}
+
+/// The total size status line, saying when sizes are allocated blocks rather
+/// than lengths, since those differ from what `ls` or `du -b` report.
+fn total_size(args: &Args, size: u64) -> String {
+    if args.physical_size {
+        format!("Total size: {} (allocated on disk)", ByteSize::b(size))
+    } else {
+        format!("Total size: {}", ByteSize::b(size))
+    }
+}
+
+/// Everything after the tree is built: writing the output and running the
+/// requested reports.
+fn run(args: Args, mut root: FileNode, root_path: PathBuf, duration: Duration) -> io::Result<()> {
+    let display_root = display_root(&args, &root_path)?;
+    if args.summary_only {
+        status(&args, &total_size(&args, root.size));
+        status(&args, &format!("File count: {}", root.file_count));
+        status(&args, &format!("Directory count: {}", root.dir_count));
+        status(&args, &format!("Time taken to index: {:?}", duration));
+        return Ok(());
+    }
+
+    #[cfg(feature = "server")]
+    if let Some(addr) = args.serve.clone() {
+        return serve(args, root, display_root, &addr);
+    }
+
+    // Before anything drops children, which might hide links.
+    let double_counted = (args.load.is_none() && args.follow_symlinks)
+        .then(|| double_counted(&root, &root_path))
+        .filter(|double_counted| double_counted.repeated > 0);
+    if let Some(threshold) = args.rollup_below {
+        let rolled_up = root.rollup_below(threshold);
+        if rolled_up > 0 {
+            status(
+                &args,
+                &format!(
+                    "Rolled up {} entries smaller than {}",
+                    rolled_up,
+                    ByteSize::b(threshold)
+                ),
+            );
+        }
+    }
+    if let Some(budget) = args.max_output_size {
+        let summary = root.collapse_to_budget(budget, |root| json_size(root, args.json_compact));
+        if summary.collapsed > 0 {
+            status(
+                &args,
+                &format!(
+                    "Collapsed {} directories ({} nodes dropped) for a {} budget",
+                    summary.collapsed,
+                    summary.dropped,
+                    ByteSize::b(budget)
+                ),
+            );
+        }
+        if !summary.fits {
+            eprintln!(
+                "Warning: output is still {} with every directory collapsed",
+                ByteSize::b(json_size(&root, args.json_compact))
+            );
+        }
+    }
+    let output = args
+        .output
+        .as_deref()
+        .unwrap_or(args.format.default_output());
+    let destination = if output == STDOUT { "stdout" } else { output };
+    if let Some(load) = &args.load {
+        status(&args, &format!("File tree has been loaded from {}", load));
+        status(&args, &format!("Time taken to load: {:?}", duration));
+        // A loaded tree is only written back out when asked to, e.g. to
+        // convert it to another format.
+        if args.output.is_some() {
+            write_output(&args, &root, &display_root, output)?;
+            status(
+                &args,
+                &format!("File tree has been saved to {}", destination),
+            );
+        }
+    } else {
+        write_output(&args, &root, &display_root, output)?;
+        // println!("{:#?}", root);
+        status(
+            &args,
+            &format!("File tree has been indexed and saved to {}", destination),
+        );
+        status(&args, &format!("Time taken to index: {:?}", duration));
+    }
+    status(&args, &total_size(&args, root.size));
+    if let Some(double_counted) = double_counted {
+        eprintln!(
+            "Warning: {} of {} followed symlinks lead to entries already counted, so about {} \
+             of the total is counted more than once (--no-follow-symlinks avoids this)",
+            double_counted.repeated,
+            double_counted.links,
+            ByteSize::b(double_counted.size)
+        );
+    }
+    // A single file has no depth or structure to report, nor anything to
+    // search but its own name.
+    let file_root = matches!(root.node_type, NodeType::File);
+    if file_root {
+        status(&args, &format!("File count: {}", root.file_count));
+    } else {
+        let (deepest, max_depth) = deepest_path(&root);
+        status(
+            &args,
+            &format!(
+                "Max depth: {} (deepest path: {})",
+                max_depth,
+                args.path_style.render(&display_root, &deepest)
+            ),
+        );
+    }
+    if let SizeMetric::Weighted { block_overhead } = args.size_metric {
+        status(
+            &args,
+            &format!(
+                "Weighted cost: {} ({} files at {} overhead each)",
+                ByteSize::b(args.size_metric.of(&root)),
+                root.file_count,
+                ByteSize::b(block_overhead)
+            ),
+        );
+    }
+
+    if let Some(dir) = &args.output_dir_stats {
+        let written = write_dir_stats(&root, Path::new(dir))?;
+        status(
+            &args,
+            &format!("Wrote {} directory sizes to {}", written, dir),
+        );
+    }
+
+    if args.verify {
+        let mismatches = root.verify_sizes();
+        if mismatches.is_empty() {
+            status(&args, "Verified: directory sizes match their leaves");
+        } else {
+            for mismatch in &mismatches {
+                eprintln!(
+                    "Size mismatch at {}: stored {} but leaves sum to {}",
+                    args.path_style.render(&display_root, &mismatch.path),
+                    mismatch.stored,
+                    mismatch.recomputed
+                );
+            }
+            eprintln!(
+                "Verification failed: root size {} vs recomputed {}",
+                root.size,
+                root.leaf_size()
+            );
+            std::process::exit(1);
+        }
+    }
+
+    if let Some(limit) = args.fail_over {
+        let mut over: Vec<_> = root.files().filter(|(_, file)| file.size > limit).collect();
+        over.sort_by_key(|(_, file)| std::cmp::Reverse(file.size));
+        for (path, file) in &over {
+            println!(
+                "{}\t{}",
+                ByteSize::b(file.size),
+                args.path_style.render(&display_root, path)
+            );
+        }
+        if !over.is_empty() {
+            eprintln!("{} files are over {}", over.len(), ByteSize::b(limit));
+            std::process::exit(1);
+        }
+        status(&args, &format!("No file is over {}", ByteSize::b(limit)));
+    }
+
+    if let Some(expected) = &args.assert_matches {
+        let expected = load_to_compare(&args, expected)?;
+        let diff = diff_trees(&expected, &root);
+        for path in &diff.added {
+            println!("added\t{}", args.path_style.render(&display_root, path));
+        }
+        for path in &diff.removed {
+            println!("removed\t{}", args.path_style.render(&display_root, path));
+        }
+        for changed in &diff.changed {
+            println!(
+                "changed\t{}\t{}",
+                args.path_style.render(&display_root, &changed.path),
+                changed.detail
+            );
+        }
+        if !diff.is_clean() {
+            std::process::exit(1);
+        }
+        status(&args, "Tree matches the expected tree");
+    }
+
+    if let Some(old) = &args.explain_growth {
+        let old = load_to_compare(&args, old)?;
+        for delta in size_deltas(&old, &root) {
+            println!(
+                "{}\t{}",
+                signed_size(delta.change()),
+                args.path_style.render(&display_root, &delta.path)
+            );
+        }
+        status(
+            &args,
+            &format!(
+                "Total size changed by {} ({} -> {})",
+                signed_size(root.size as i128 - old.size as i128),
+                ByteSize::b(old.size),
+                ByteSize::b(root.size)
+            ),
+        );
+    }
+
+    if let Some(manifest) = &args.manifest {
+        let mut writer = BufWriter::new(File::create(manifest)?);
+        write_manifest(&root, &mut writer)?;
+        status(&args, &format!("Manifest written to {}", manifest));
+    }
+
+    if let Some(manifest) = &args.verify_manifest {
+        let expected = read_manifest(BufReader::new(File::open(manifest)?))?;
+        let report = verify_manifest(&root, &expected);
+        for path in &report.mismatched {
+            println!(
+                "mismatched\t{}",
+                args.path_style.render(&display_root, path)
+            );
+        }
+        for path in &report.missing {
+            println!("missing\t{}", args.path_style.render(&display_root, path));
+        }
+        for path in &report.new {
+            println!("new\t{}", args.path_style.render(&display_root, path));
+        }
+        if !report.is_clean() {
+            std::process::exit(1);
+        }
+        status(&args, &format!("All files match {}", manifest));
+    }
+
+    if let Some(manifest) = &args.check_manifest {
+        let expected = read_manifest(BufReader::new(File::open(manifest)?))?;
+        let report = find_stale(&root, &expected);
+        for path in &report.resized {
+            println!("resized\t{}", args.path_style.render(&display_root, path));
+        }
+        for path in &report.needs_verification {
+            println!(
+                "needs verification\t{}",
+                args.path_style.render(&display_root, path)
+            );
+        }
+        for path in &report.missing {
+            println!("missing\t{}", args.path_style.render(&display_root, path));
+        }
+        for path in &report.new {
+            println!("new\t{}", args.path_style.render(&display_root, path));
+        }
+        if !report.is_clean() {
+            std::process::exit(1);
+        }
+        status(&args, &format!("All sizes and mtimes match {}", manifest));
+    }
+
+    if let Some(path) = &args.get {
+        let Some(node) = root.get(path) else {
+            eprintln!("No node at {}", path);
+            std::process::exit(1);
+        };
+        print_node(path, node);
+    }
+
+    if args.fragmentation {
+        println!("path\tfiles\tmean\tmedian\tstd_dev\tunder_4k");
+        for stats in fragmentation(&root) {
+            println!(
+                "{}\t{}\t{}\t{}\t{}\t{}",
+                args.path_style.render(&display_root, &stats.path),
+                stats.files,
+                ByteSize::b(stats.mean.round() as u64),
+                ByteSize::b(stats.median.round() as u64),
+                ByteSize::b(stats.std_dev.round() as u64),
+                stats.small_files
+            );
+        }
+    }
+
+    if args.type_summary {
+        println!("type\tnodes\tsize");
+        for (node_type, total) in type_summary(&root) {
+            println!(
+                "{}\t{}\t{}",
+                node_type,
+                total.nodes,
+                ByteSize::b(total.size)
+            );
+        }
+    }
+
+    if let Some(threshold) = args.hotspots {
+        let mut found = hotspots(&root, threshold);
+        found.sort_by_key(|(_, node)| std::cmp::Reverse(node.size));
+        for (path, node) in &found {
+            println!(
+                "{}\t{}",
+                ByteSize::b(node.size),
+                args.path_style.render(&display_root, path)
+            );
+        }
+        status(
+            &args,
+            &format!(
+                "Found {} hotspots of at least {}",
+                found.len(),
+                ByteSize::b(threshold)
+            ),
+        );
+    }
+
+    if let Some(threshold) = args.wide_dirs {
+        let found = wide_dirs(&root, threshold);
+        for (path, dir) in &found {
+            println!(
+                "{}\t{}",
+                dir.children.len(),
+                args.path_style.render(&display_root, path)
+            );
+        }
+        status(
+            &args,
+            &format!(
+                "Found {} directories with more than {} entries",
+                found.len(),
+                threshold
+            ),
+        );
+    }
+
+    if let Some(n) = args.largest_dirs {
+        let largest = largest_dirs(&root, n, args.exclude_ancestors);
+        for (path, dir) in &largest {
+            println!(
+                "{}\t{}",
+                ByteSize::b(dir.size),
+                args.path_style.render(&display_root, path)
+            );
+        }
+        status(
+            &args,
+            &format!("Listed the {} largest directories", largest.len()),
+        );
+    }
+
+    if args.find_broken_symlinks {
+        let mut broken = 0;
+        for (path, node) in root.all() {
+            if let NodeType::Symlink {
+                target,
+                resolved: None,
+            } = &node.node_type
+            {
+                println!(
+                    "{}\t-> {}",
+                    args.path_style.render(&display_root, &path),
+                    slash_path(target)
+                );
+                broken += 1;
+            }
+        }
+        status(&args, &format!("Found {} broken symlinks", broken));
+    }
+
+    if args.find_name_collisions {
+        let mut collisions: Vec<_> = find_name_collisions(&root).into_iter().collect();
+        collisions.sort_unstable();
+        for (name, paths) in &collisions {
+            println!("{}\t{} files", name, paths.len());
+            for path in paths {
+                println!("  {}", args.path_style.render(&display_root, path));
+            }
+        }
+        status(
+            &args,
+            &format!(
+                "Found {} file names in more than one directory",
+                collisions.len()
+            ),
+        );
+    }
+
+    if args.find_case_collisions {
+        let collisions = find_case_collisions(&root);
+        for collision in &collisions {
+            println!(
+                "{}\t{} entries",
+                args.path_style.render(&display_root, &collision.dir),
+                collision.paths.len()
+            );
+            for path in &collision.paths {
+                println!("  {}", args.path_style.render(&display_root, path));
+            }
+        }
+        status(
+            &args,
+            &format!(
+                "Found {} sets of names differing only in case",
+                collisions.len()
+            ),
+        );
+    }
+
+    if args.find_duplicate_dirs {
+        let duplicates = find_duplicate_dirs(&root);
+        for group in &duplicates {
+            println!("{}\t{} copies", ByteSize::b(group.size), group.paths.len());
+            for path in &group.paths {
+                println!("  {}", args.path_style.render(&display_root, path));
+            }
+        }
+        status(
+            &args,
+            &format!(
+                "Found {} sets of directories with identical contents",
+                duplicates.len()
+            ),
+        );
+    }
+
+    if args.find_duplicates {
+        let duplicates = find_duplicate_files(&root, &root_path, args.dedup_min_size);
+        for paths in &duplicates {
+            let size = root.get(&paths[0]).map_or(0, |file| file.size);
+            println!("{}\t{} copies", ByteSize::b(size), paths.len());
+            for path in paths {
+                println!("  {}", args.path_style.render(&display_root, path));
+            }
+        }
+        status(
+            &args,
+            &format!(
+                "Found {} sets of files with identical contents",
+                duplicates.len()
+            ),
+        );
+    }
+
+    if !args.find_empty_suspicious.is_empty() {
+        let suspicious = find_suspicious_empty(&root, &args.find_empty_suspicious);
+        for path in &suspicious {
+            println!("{}", args.path_style.render(&display_root, path));
+        }
+        if !suspicious.is_empty() {
+            eprintln!("{} files are empty but shouldn't be", suspicious.len());
+            std::process::exit(1);
+        }
+        status(&args, "No file that shouldn't be empty is empty");
+    }
+
+    if args.prune_empty {
+        let empty = find_empty(&root, &root_path);
+        if args.apply {
+            let failed = remove_empty(&root_path, &empty);
+            for entry in &empty {
+                if !failed.iter().any(|(path, _)| *path == entry.path) {
+                    println!(
+                        "removed\t{}",
+                        args.path_style.render(&display_root, &entry.path)
+                    );
+                }
+            }
+            for (path, err) in &failed {
+                eprintln!(
+                    "Failed to remove {}: {}",
+                    args.path_style.render(&display_root, path),
+                    err
+                );
+            }
+            if !failed.is_empty() {
+                std::process::exit(1);
+            }
+        } else {
+            for entry in &empty {
+                println!(
+                    "would remove\t{}",
+                    args.path_style.render(&display_root, &entry.path)
+                );
+            }
+            status(
+                &args,
+                &format!(
+                    "{} entries would be removed; pass --apply to delete them",
+                    empty.len()
+                ),
+            );
+        }
+    }
+
+    if args.repl {
+        return repl(&args, &root, &display_root);
+    }
 
     // Example of fuzzy search
-    let search_query = "example";
-    let search_results = fuzzy_search(&root, search_query);
-    println!(
-        "Fuzzy search results for '{}': {:?}",
-        search_query, search_results
-    );
+    let search_query = &args.search_query;
+    if file_root {
+        status(&args, "Not searching: the root is a single file");
+    } else if writes_stdout(&args) {
+        status(&args, "Not searching: stdout holds the output");
+    } else if let Some(pattern) = &args.glob {
+        let matches = glob_search(&root, pattern, args.glob_path).map_err(io::Error::other)?;
+        let terminator = if args.print0 { "\0" } else { "\n" };
+        let mut stdout = io::stdout().lock();
+        for path in &matches {
+            let path = args
+                .search_options
+                .render(&root, args.path_style, &display_root, path);
+            write!(stdout, "{}{}", path, terminator)?;
+        }
+        stdout.flush()?;
+        status(
+            &args,
+            &format!("Found {} files matching '{}'", matches.len(), pattern),
+        );
+    } else if args.print0 {
+        print_matches0(&args, &root, &display_root)?;
+    } else {
+        let search_results: Vec<String> = fuzzy_search(&root, search_query, &args.search_options)
+            .iter()
+            .map(|path| {
+                args.search_options
+                    .render(&root, args.path_style, &display_root, path)
+            })
+            .collect();
+        println!(
+            "Fuzzy search results for '{}': {:?}",
+            search_query, search_results
+        );
+    }
 
     Ok(())
 }
@@ -0,0 +1,202 @@
+//! Integrity manifests: a sorted `path<TAB>size<TAB>mtime<TAB>hash` listing
+//! of every file in a tree, and checks of the current tree against a saved
+//! manifest.
+//!
+//! Paths are relative to the indexed root, so a manifest can be verified
+//! against the same folder wherever it is mounted. Times are milliseconds
+//! since the Unix epoch, empty when unknown. Manifests written before the
+//! `mtime` column was added still read, without times.
+
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+use crate::flat::millis;
+use crate::{join_relative, FileNode, NodeType};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ManifestEntry {
+    pub size: u64,
+    /// Modification time in milliseconds since the Unix epoch.
+    pub mtime: Option<i64>,
+    pub hash: String,
+}
+
+/// Differences between a saved manifest and the current tree.
+#[derive(Debug, Default)]
+pub struct ManifestReport {
+    /// Files whose size or hash changed.
+    pub mismatched: Vec<String>,
+    /// Files listed in the manifest that no longer exist.
+    pub missing: Vec<String>,
+    /// Files that exist but are not listed in the manifest.
+    pub new: Vec<String>,
+}
+
+impl ManifestReport {
+    pub fn is_clean(&self) -> bool {
+        self.mismatched.is_empty() && self.missing.is_empty() && self.new.is_empty()
+    }
+}
+
+/// What a saved manifest says about the current tree without hashing it.
+#[derive(Debug, Default)]
+pub struct StaleReport {
+    /// Files whose size changed, so their content certainly did.
+    pub resized: Vec<String>,
+    /// Files of the same size but a different mtime, whose content may or
+    /// may not have changed. Only these need re-hashing to find out.
+    pub needs_verification: Vec<String>,
+    /// Files listed in the manifest that no longer exist.
+    pub missing: Vec<String>,
+    /// Files that exist but are not listed in the manifest.
+    pub new: Vec<String>,
+}
+
+impl StaleReport {
+    pub fn is_clean(&self) -> bool {
+        self.resized.is_empty()
+            && self.needs_verification.is_empty()
+            && self.missing.is_empty()
+            && self.new.is_empty()
+    }
+}
+
+/// Collects the manifest entries of every hashed file in the tree, keyed by
+/// path relative to the root. Files without a hash (e.g. unreadable ones) are
+/// left out; call [`FileNode::compute_hashes`] first.
+pub fn manifest_entries(root: &FileNode) -> BTreeMap<String, ManifestEntry> {
+    fn collect(node: &FileNode, path: &str, entries: &mut BTreeMap<String, ManifestEntry>) {
+        match node.node_type {
+            NodeType::File => {
+                if let Some(hash) = &node.hash {
+                    let entry = ManifestEntry {
+                        size: node.size,
+                        mtime: millis(node.mtime),
+                        hash: hash.clone(),
+                    };
+                    entries.insert(path.to_string(), entry);
+                }
+            }
+            NodeType::Directory => {
+                for child in &node.children {
+                    collect(child, &join_relative(path, &child.name), entries);
+                }
+            }
+            NodeType::IgnoredDirectory
+            | NodeType::CollapsedDirectory
+            | NodeType::Special
+            | NodeType::Symlink { .. } => {}
+        }
+    }
+
+    let mut entries = BTreeMap::new();
+    collect(root, "", &mut entries);
+    entries
+}
+
+/// Writes the manifest of `root`, sorted by path.
+pub fn write_manifest<W: Write>(root: &FileNode, writer: &mut W) -> io::Result<()> {
+    for (path, entry) in manifest_entries(root) {
+        let mtime = entry
+            .mtime
+            .map(|mtime| mtime.to_string())
+            .unwrap_or_default();
+        writeln!(
+            writer,
+            "{}\t{}\t{}\t{}",
+            path, entry.size, mtime, entry.hash
+        )?;
+    }
+    writer.flush()
+}
+
+/// Parses a manifest written by [`write_manifest`].
+pub fn read_manifest<R: BufRead>(reader: R) -> io::Result<BTreeMap<String, ManifestEntry>> {
+    let mut entries = BTreeMap::new();
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.is_empty() {
+            continue;
+        }
+        let invalid = || {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid manifest line {}: {}", number + 1, line),
+            )
+        };
+        // Split from the right so paths containing tabs still parse. A line
+        // whose size and mtime fields both parse has an mtime column;
+        // otherwise it is from a manifest without one.
+        let four: Vec<&str> = line.rsplitn(4, '\t').collect();
+        let (path, size, mtime, hash) = match four[..] {
+            [hash, mtime, size, path]
+                if size.parse::<u64>().is_ok()
+                    && (mtime.is_empty() || mtime.parse::<i64>().is_ok()) =>
+            {
+                (path, size, mtime.parse().ok(), hash)
+            }
+            _ => {
+                let three: Vec<&str> = line.rsplitn(3, '\t').collect();
+                let [hash, size, path] = three[..] else {
+                    return Err(invalid());
+                };
+                (path, size, None, hash)
+            }
+        };
+        let entry = ManifestEntry {
+            size: size.parse().map_err(|_| invalid())?,
+            mtime,
+            hash: hash.to_string(),
+        };
+        entries.insert(path.to_string(), entry);
+    }
+    Ok(entries)
+}
+
+/// Compares the hashed tree `root` against a saved manifest.
+pub fn verify_manifest(
+    root: &FileNode,
+    expected: &BTreeMap<String, ManifestEntry>,
+) -> ManifestReport {
+    let current = manifest_entries(root);
+    let mut report = ManifestReport::default();
+    for (path, entry) in expected {
+        match current.get(path) {
+            Some(current_entry)
+                if current_entry.size == entry.size && current_entry.hash == entry.hash => {}
+            Some(_) => report.mismatched.push(path.clone()),
+            None => report.missing.push(path.clone()),
+        }
+    }
+    for path in current.keys() {
+        if !expected.contains_key(path) {
+            report.new.push(path.clone());
+        }
+    }
+    report
+}
+
+/// Compares the sizes and mtimes of the files in `root`, which need not be
+/// hashed, against a saved manifest, to find the few files worth re-hashing.
+/// Entries without an mtime on either side are only compared by size.
+pub fn find_stale(root: &FileNode, expected: &BTreeMap<String, ManifestEntry>) -> StaleReport {
+    let current: BTreeMap<String, &FileNode> = root.files().collect();
+    let mut report = StaleReport::default();
+    for (path, entry) in expected {
+        match current.get(path) {
+            None => report.missing.push(path.clone()),
+            Some(file) if file.size != entry.size => report.resized.push(path.clone()),
+            Some(file) => match (millis(file.mtime), entry.mtime) {
+                (Some(now), Some(then)) if now != then => {
+                    report.needs_verification.push(path.clone())
+                }
+                _ => {}
+            },
+        }
+    }
+    for path in current.keys() {
+        if !expected.contains_key(path) {
+            report.new.push(path.clone());
+        }
+    }
+    report
+}
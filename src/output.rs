@@ -0,0 +1,288 @@
+//! Output formats behind one trait, so the CLI picks a value instead of
+//! matching on every format, and library users can write trees in formats of
+//! their own through the same interface.
+//!
+//! ```
+//! use std::io::{self, Write};
+//! use fs_index::output::OutputFormat;
+//! use fs_index::{FileNode, NodeType};
+//!
+//! /// Only the total size, as a bare number.
+//! struct Total;
+//!
+//! impl OutputFormat for Total {
+//!     fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+//!         writeln!(writer, "{}", root.size)?;
+//!         writer.flush()
+//!     }
+//! }
+//!
+//! let mut root = FileNode::new("root".into(), 0, NodeType::Directory);
+//! root.add_child(FileNode::new("a.txt".into(), 42, NodeType::File));
+//! let mut out = Vec::new();
+//! Total.write(&root, &mut out).unwrap();
+//! assert_eq!(out, b"42\n");
+//! ```
+
+use std::io::{self, Write};
+use std::path::PathBuf;
+use bytesize::ByteSize;
+use serde::Serialize;
+use crate::flat::{flatten, size_map, write_csv, write_ndjson, Summary};
+use crate::{FileNode, NodeType, PathStyle};
+
+/// A way of writing a whole tree.
+pub trait OutputFormat {
+    /// Writes `root` to `writer`, which is flushed before returning.
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()>;
+}
+
+/// Where flat formats render paths from.
+#[derive(Debug, Clone)]
+pub struct FlatPaths {
+    /// The path the tree was indexed from.
+    pub root_path: PathBuf,
+    pub style: PathStyle,
+}
+
+/// The tree as nested JSON objects.
+#[derive(Debug, Clone, Default)]
+pub struct Json {
+    /// Write on one line instead of pretty-printing.
+    pub compact: bool,
+}
+
+impl Json {
+    /// Writes any value as JSON, pretty-printed unless `compact` is set.
+    pub fn write_value<T: Serialize>(&self, value: &T, writer: &mut dyn Write) -> io::Result<()> {
+        if self.compact {
+            serde_json::to_writer(&mut *writer, value)?;
+        } else {
+            serde_json::to_writer_pretty(&mut *writer, value)?;
+        }
+        writer.flush()
+    }
+}
+
+impl OutputFormat for Json {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        self.write_value(root, writer)
+    }
+}
+
+/// A single JSON object mapping paths to sizes, see [`size_map`].
+#[derive(Debug, Clone)]
+pub struct FlatMap {
+    pub paths: FlatPaths,
+    /// Include every node, not only files.
+    pub all_nodes: bool,
+    pub json: Json,
+}
+
+impl OutputFormat for FlatMap {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        let rows = flatten(root, &self.paths.root_path, self.paths.style);
+        self.json
+            .write_value(&size_map(&rows, self.all_nodes)?, writer)
+    }
+}
+
+/// One CSV record per node, see [`write_csv`].
+#[derive(Debug, Clone)]
+pub struct Csv {
+    pub paths: FlatPaths,
+    /// Start with a [`Summary`] comment line.
+    pub summary: bool,
+}
+
+impl OutputFormat for Csv {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        let rows = flatten(root, &self.paths.root_path, self.paths.style);
+        let summary = self
+            .summary
+            .then(|| Summary::of(root, &self.paths.root_path));
+        write_csv(&rows, summary.as_ref(), writer)
+    }
+}
+
+/// One JSON object per node and line, see [`write_ndjson`].
+#[derive(Debug, Clone)]
+pub struct Ndjson {
+    pub paths: FlatPaths,
+    /// Start with a [`Summary`] line.
+    pub summary: bool,
+}
+
+impl OutputFormat for Ndjson {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        let rows = flatten(root, &self.paths.root_path, self.paths.style);
+        let summary = self
+            .summary
+            .then(|| Summary::of(root, &self.paths.root_path));
+        write_ndjson(&rows, summary.as_ref(), writer)
+    }
+}
+
+/// The path of every file, one per line and nothing else, like
+/// `find -type f`. Directories, symlinks and special files are left out.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    pub paths: FlatPaths,
+    /// End each path with `\0` instead of a newline, for `xargs -0`.
+    pub nul: bool,
+}
+
+impl OutputFormat for Paths {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        let terminator: &[u8] = if self.nul { b"\0" } else { b"\n" };
+        for (path, _) in root.files() {
+            let path = self.paths.style.render(&self.paths.root_path, &path);
+            writer.write_all(path.as_bytes())?;
+            writer.write_all(terminator)?;
+        }
+        writer.flush()
+    }
+}
+
+/// Every directory with its total size, largest first and one per line, like
+/// `du` sorted: the size as [`ByteSize`], right-aligned, then the path.
+/// Gitignored and collapsed directories are listed with their totals but
+/// nothing below them, and the root comes as `.` with relative paths. Ties
+/// are in tree order.
+#[derive(Debug, Clone)]
+pub struct Du {
+    pub paths: FlatPaths,
+}
+
+impl OutputFormat for Du {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        let mut dirs: Vec<_> = root
+            .all()
+            .filter(|(_, node)| {
+                matches!(
+                    node.node_type,
+                    NodeType::Directory | NodeType::IgnoredDirectory | NodeType::CollapsedDirectory
+                )
+            })
+            .collect();
+        dirs.sort_by_key(|(_, dir)| std::cmp::Reverse(dir.size));
+        for (path, dir) in dirs {
+            let path = self.paths.style.render(&self.paths.root_path, &path);
+            writeln!(
+                writer,
+                "{:>10}  {}",
+                ByteSize::b(dir.size).to_string(),
+                path
+            )?;
+        }
+        writer.flush()
+    }
+}
+
+/// The tree as a GraphViz digraph, for `dot -Tpng`: one node per tree node,
+/// labeled with its name and size, and an edge from each directory to each of
+/// its children. Directories are drawn as boxes. Trees of more than a few
+/// hundred nodes are best limited with [`crate::IndexConfig::max_depth`].
+#[derive(Debug, Clone, Default)]
+pub struct Dot;
+
+impl OutputFormat for Dot {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        fn write_node(
+            node: &FileNode,
+            parent: Option<usize>,
+            next_id: &mut usize,
+            writer: &mut dyn Write,
+        ) -> io::Result<()> {
+            let id = *next_id;
+            *next_id += 1;
+            let shape = match node.node_type {
+                NodeType::File | NodeType::Special | NodeType::Symlink { .. } => "ellipse",
+                _ => "box",
+            };
+            writeln!(
+                writer,
+                "  n{} [label=\"{}\\n{}\", shape={}];",
+                id,
+                dot_escape(&node.name),
+                ByteSize::b(node.size),
+                shape
+            )?;
+            if let Some(parent) = parent {
+                writeln!(writer, "  n{} -> n{};", parent, id)?;
+            }
+            for child in &node.children {
+                write_node(child, Some(id), next_id, writer)?;
+            }
+            Ok(())
+        }
+
+        writeln!(writer, "digraph tree {{")?;
+        writeln!(writer, "  rankdir=LR;")?;
+        write_node(root, None, &mut 0, writer)?;
+        writeln!(writer, "}}")?;
+        writer.flush()
+    }
+}
+
+/// Escapes `text` for a double-quoted DOT string, where a backslash would
+/// otherwise start an escape sequence and a line break would end up in the
+/// label verbatim.
+fn dot_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// The tree as nested YAML mappings.
+#[cfg(feature = "yaml")]
+#[derive(Debug, Clone, Default)]
+pub struct Yaml;
+
+#[cfg(feature = "yaml")]
+impl OutputFormat for Yaml {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        serde_yaml::to_writer(&mut *writer, root).map_err(io::Error::other)?;
+        writer.flush()
+    }
+}
+
+/// The tree as one protobuf message, see [`crate::protobuf`].
+#[cfg(feature = "protobuf")]
+#[derive(Debug, Clone, Default)]
+pub struct Protobuf;
+
+#[cfg(feature = "protobuf")]
+impl OutputFormat for Protobuf {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&crate::protobuf::encode(root))?;
+        writer.flush()
+    }
+}
+
+/// One Parquet row per node, see [`crate::flat::write_parquet`]. The file is
+/// built in memory first, since the Parquet writer needs a `Send` writer.
+#[cfg(feature = "parquet")]
+#[derive(Debug, Clone)]
+pub struct Parquet {
+    pub paths: FlatPaths,
+}
+
+#[cfg(feature = "parquet")]
+impl OutputFormat for Parquet {
+    fn write(&self, root: &FileNode, writer: &mut dyn Write) -> io::Result<()> {
+        let rows = flatten(root, &self.paths.root_path, self.paths.style);
+        let mut buffer = Vec::new();
+        crate::flat::write_parquet(&rows, &mut buffer).map_err(io::Error::other)?;
+        writer.write_all(&buffer)?;
+        writer.flush()
+    }
+}
@@ -0,0 +1,81 @@
+//! Percentage progress for long indexing and hashing runs. For indexing the
+//! total is not known until the walk finishes, so [`count_entries`] makes a
+//! cheap first pass to estimate it. Hashing counts bytes instead, against the
+//! sizes the walk found, since a single huge file can take longer than
+//! thousands of small ones.
+
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use rayon::prelude::*;
+
+/// Entries indexed or bytes hashed so far against an expected total, shared
+/// by the worker threads.
+#[derive(Debug)]
+pub struct Progress {
+    total: u64,
+    done: AtomicU64,
+    started: Instant,
+}
+
+impl Progress {
+    /// Starts the clock for [`Progress::eta`].
+    pub fn new(total: u64) -> Self {
+        Progress {
+            total,
+            done: AtomicU64::new(0),
+            started: Instant::now(),
+        }
+    }
+
+    pub(crate) fn add(&self, entries: u64) {
+        self.done.fetch_add(entries, Ordering::Relaxed);
+    }
+
+    pub fn done(&self) -> u64 {
+        self.done.load(Ordering::Relaxed)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    /// Percentage of the total indexed so far. Capped at 100, since the
+    /// tree may have grown since it was counted.
+    pub fn percent(&self) -> u64 {
+        match self.total {
+            0 => 100,
+            total => (self.done() * 100 / total).min(100),
+        }
+    }
+
+    /// Time left at the average rate so far, `None` until anything is done.
+    pub fn eta(&self) -> Option<Duration> {
+        let done = self.done().min(self.total);
+        if done == 0 {
+            return None;
+        }
+        let elapsed = self.started.elapsed();
+        Some(elapsed.mul_f64((self.total - done) as f64 / done as f64))
+    }
+}
+
+/// Counts `path` and every entry below it, taking entry types from the
+/// directory listings so nothing is stat'ed. Unreadable directories count
+/// as a single entry. Gitignored directories are counted in full, since
+/// sizing them visits every entry too.
+pub fn count_entries(path: &Path) -> u64 {
+    let Ok(entries) = fs::read_dir(path) else {
+        return 1;
+    };
+    1 + entries
+        .par_bridge()
+        .map(|entry| match entry {
+            Ok(entry) if entry.file_type().is_ok_and(|t| t.is_dir()) => {
+                count_entries(&entry.path())
+            }
+            _ => 1,
+        })
+        .sum::<u64>()
+}
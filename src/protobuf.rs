@@ -0,0 +1,214 @@
+//! The tree as protobuf messages, for typed consumers such as Go or Java
+//! services. The schema is `proto/fs_index.proto` in the repository; the
+//! message types here are written to match it, so building needs no
+//! `protoc`, and other languages generate theirs from the same file.
+//!
+//! Decoders typically stop at 100 levels of nesting, prost's included, so a
+//! tree deeper than that needs a raised limit on the decoding side or
+//! [`crate::IndexConfig::max_depth`].
+//!
+//! ```
+//! use fs_index::{protobuf, FileNode, NodeType};
+//!
+//! let mut root = FileNode::new("root".into(), 0, NodeType::Directory);
+//! root.add_child(FileNode::new("a.txt".into(), 42, NodeType::File));
+//! let decoded = protobuf::decode(&protobuf::encode(&root)).unwrap();
+//! assert_eq!(decoded.size, 42);
+//! assert_eq!(decoded.children[0].name, "a.txt");
+//! ```
+
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use prost::Message;
+use crate::slash_path;
+
+/// `fs_index.NodeType`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum NodeType {
+    File = 0,
+    Directory = 1,
+    IgnoredDirectory = 2,
+    CollapsedDirectory = 3,
+    Special = 4,
+    Symlink = 5,
+}
+
+/// `fs_index.Timestamp`, seconds and nanoseconds since the Unix epoch.
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct Timestamp {
+    #[prost(int64, tag = "1")]
+    pub seconds: i64,
+    #[prost(uint32, tag = "2")]
+    pub nanos: u32,
+}
+
+/// `fs_index.FileNode`, see [`crate::FileNode`] for what the fields mean.
+#[derive(Clone, PartialEq, Message)]
+pub struct FileNode {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(uint64, tag = "2")]
+    pub size: u64,
+    #[prost(uint64, tag = "3")]
+    pub indexed_size: u64,
+    #[prost(uint64, tag = "4")]
+    pub ignored_size: u64,
+    #[prost(uint64, tag = "5")]
+    pub file_count: u64,
+    #[prost(uint64, tag = "6")]
+    pub dir_count: u64,
+    #[prost(enumeration = "NodeType", tag = "7")]
+    pub node_type: i32,
+    /// The target stored in the link, for [`NodeType::Symlink`] only.
+    #[prost(string, optional, tag = "8")]
+    pub symlink_target: Option<String>,
+    /// Where a [`NodeType::Symlink`] leads, `None` if it is broken.
+    #[prost(string, optional, tag = "9")]
+    pub symlink_resolved: Option<String>,
+    #[prost(message, optional, tag = "10")]
+    pub mtime: Option<Timestamp>,
+    #[prost(message, optional, tag = "11")]
+    pub atime: Option<Timestamp>,
+    #[prost(message, optional, tag = "12")]
+    pub btime: Option<Timestamp>,
+    #[prost(string, optional, tag = "13")]
+    pub link_target: Option<String>,
+    #[prost(string, optional, tag = "14")]
+    pub hash: Option<String>,
+    #[prost(string, optional, tag = "15")]
+    pub signature: Option<String>,
+    #[prost(string, optional, tag = "16")]
+    pub path: Option<String>,
+    #[prost(message, repeated, tag = "17")]
+    pub children: Vec<FileNode>,
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(since) => Timestamp {
+                seconds: since.as_secs() as i64,
+                nanos: since.subsec_nanos(),
+            },
+            // Before the epoch, nanos still count forwards from `seconds`.
+            Err(err) => {
+                let before = err.duration();
+                let seconds = -(before.as_secs() as i64);
+                match before.subsec_nanos() {
+                    0 => Timestamp { seconds, nanos: 0 },
+                    nanos => Timestamp {
+                        seconds: seconds - 1,
+                        nanos: 1_000_000_000 - nanos,
+                    },
+                }
+            }
+        }
+    }
+}
+
+impl From<Timestamp> for SystemTime {
+    fn from(time: Timestamp) -> Self {
+        let since = UNIX_EPOCH + Duration::from_nanos(time.nanos as u64);
+        if time.seconds >= 0 {
+            since + Duration::from_secs(time.seconds as u64)
+        } else {
+            since - Duration::from_secs(time.seconds.unsigned_abs())
+        }
+    }
+}
+
+impl From<&crate::FileNode> for FileNode {
+    fn from(node: &crate::FileNode) -> Self {
+        let (node_type, symlink_target, symlink_resolved) = match &node.node_type {
+            crate::NodeType::File => (NodeType::File, None, None),
+            crate::NodeType::Directory => (NodeType::Directory, None, None),
+            crate::NodeType::IgnoredDirectory => (NodeType::IgnoredDirectory, None, None),
+            crate::NodeType::CollapsedDirectory => (NodeType::CollapsedDirectory, None, None),
+            crate::NodeType::Special => (NodeType::Special, None, None),
+            crate::NodeType::Symlink { target, resolved } => (
+                NodeType::Symlink,
+                Some(slash_path(target)),
+                resolved.as_deref().map(slash_path),
+            ),
+        };
+        FileNode {
+            name: node.name.to_string(),
+            size: node.size,
+            indexed_size: node.indexed_size,
+            ignored_size: node.ignored_size,
+            file_count: node.file_count,
+            dir_count: node.dir_count,
+            node_type: node_type as i32,
+            symlink_target,
+            symlink_resolved,
+            mtime: node.mtime.map(Timestamp::from),
+            atime: node.atime.map(Timestamp::from),
+            btime: node.btime.map(Timestamp::from),
+            link_target: node.link_target.as_deref().map(slash_path),
+            hash: node.hash.clone(),
+            signature: node.signature.clone(),
+            path: node.path.clone(),
+            children: node.children.iter().map(FileNode::from).collect(),
+        }
+    }
+}
+
+impl TryFrom<FileNode> for crate::FileNode {
+    type Error = io::Error;
+
+    /// Fails on a node type this version doesn't know.
+    fn try_from(node: FileNode) -> io::Result<Self> {
+        let node_type = match NodeType::try_from(node.node_type) {
+            Ok(NodeType::File) => crate::NodeType::File,
+            Ok(NodeType::Directory) => crate::NodeType::Directory,
+            Ok(NodeType::IgnoredDirectory) => crate::NodeType::IgnoredDirectory,
+            Ok(NodeType::CollapsedDirectory) => crate::NodeType::CollapsedDirectory,
+            Ok(NodeType::Special) => crate::NodeType::Special,
+            Ok(NodeType::Symlink) => crate::NodeType::Symlink {
+                target: PathBuf::from(node.symlink_target.unwrap_or_default()),
+                resolved: node.symlink_resolved.map(PathBuf::from),
+            },
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("{} has unknown node type {}", node.name, node.node_type),
+                ))
+            }
+        };
+        Ok(crate::FileNode {
+            name: node.name.into(),
+            size: node.size,
+            indexed_size: node.indexed_size,
+            ignored_size: node.ignored_size,
+            file_count: node.file_count,
+            dir_count: node.dir_count,
+            node_type,
+            mtime: node.mtime.map(SystemTime::from),
+            atime: node.atime.map(SystemTime::from),
+            btime: node.btime.map(SystemTime::from),
+            link_target: node.link_target.map(PathBuf::from),
+            hash: node.hash,
+            signature: node.signature,
+            path: node.path,
+            children: node
+                .children
+                .into_iter()
+                .map(crate::FileNode::try_from)
+                .collect::<io::Result<_>>()?,
+        })
+    }
+}
+
+/// Encodes `root` as one `fs_index.FileNode` message.
+pub fn encode(root: &crate::FileNode) -> Vec<u8> {
+    FileNode::from(root).encode_to_vec()
+}
+
+/// Decodes a tree written by [`encode`] or `--format protobuf`.
+pub fn decode(bytes: &[u8]) -> io::Result<crate::FileNode> {
+    FileNode::decode(bytes)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?
+        .try_into()
+}
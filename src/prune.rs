@@ -0,0 +1,112 @@
+//! Finding and removing zero-byte files and empty directories, and finding
+//! zero-byte files of types that should never be empty.
+//!
+//! A directory counts as empty when everything in it is itself removable, so
+//! a directory holding only zero-byte files goes too. Ignored, collapsed and
+//! special entries are never removed and keep their parent, and so do entries
+//! the tree doesn't hold, such as gitignored or filtered out files, since
+//! each directory is listed on disk before it counts as empty. Symlinks are
+//! never followed: a symlink is never removed, and a symlinked directory is
+//! not looked into.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use crate::{join_relative, FileNode, NodeType};
+
+/// Zero-byte files whose extension, compared case-insensitively, is one of
+/// `extensions` (lowercase, without the dot), in pre-order. A `.parquet`,
+/// `.gz` or `.log` file with no content at all usually means the job that
+/// wrote it failed, so this is more telling than every empty file. Sizes come
+/// from the tree; nothing is checked on disk.
+pub fn find_suspicious_empty(root: &FileNode, extensions: &[String]) -> Vec<String> {
+    root.files()
+        .filter(|(_, file)| {
+            file.size == 0
+                && Path::new(&file.name)
+                    .extension()
+                    .is_some_and(|ext| extensions.contains(&ext.to_string_lossy().to_lowercase()))
+        })
+        .map(|(path, _)| path)
+        .collect()
+}
+
+/// A file or directory that [`remove_empty`] would delete.
+#[derive(Debug, Clone)]
+pub struct EmptyEntry {
+    /// Path relative to the root, see [`crate::PathStyle::render`].
+    pub path: String,
+    pub is_dir: bool,
+}
+
+/// Lists the removable entries below `root`, which was indexed from
+/// `root_path`, children before their directory. The root itself is never
+/// listed. Each entry is checked on disk with `symlink_metadata`, so entries
+/// the tree reached through a symlink are left out, and each directory is
+/// listed, so one holding anything the tree left out is kept.
+pub fn find_empty(root: &FileNode, root_path: &Path) -> Vec<EmptyEntry> {
+    fn collect(node: &FileNode, path: &str, root_path: &Path, found: &mut Vec<EmptyEntry>) -> bool {
+        let Ok(metadata) = fs::symlink_metadata(root_path.join(path)) else {
+            return false;
+        };
+        match node.node_type {
+            NodeType::File => metadata.is_file() && metadata.len() == 0,
+            NodeType::Directory if metadata.is_dir() => {
+                let Ok(entries) = fs::read_dir(root_path.join(path)) else {
+                    return false;
+                };
+                let names: HashSet<&str> = node
+                    .children
+                    .iter()
+                    .map(|child| child.name.as_str())
+                    .collect();
+                let mut all_removable = entries.into_iter().all(|entry| {
+                    entry.is_ok_and(|entry| names.contains(&*entry.file_name().to_string_lossy()))
+                });
+                for child in &node.children {
+                    let child_path = join_relative(path, &child.name);
+                    if collect(child, &child_path, root_path, found) {
+                        found.push(EmptyEntry {
+                            path: child_path,
+                            is_dir: matches!(child.node_type, NodeType::Directory),
+                        });
+                    } else {
+                        all_removable = false;
+                    }
+                }
+                all_removable
+            }
+            _ => false,
+        }
+    }
+
+    let mut found = Vec::new();
+    collect(root, "", root_path, &mut found);
+    found
+}
+
+/// Deletes the entries returned by [`find_empty`], in order. Each one is
+/// checked again right before deletion, so a file that has gained content or
+/// become a symlink since is skipped; directories are removed with
+/// `remove_dir`, which refuses non-empty ones. Returns the entries that
+/// couldn't be removed with the reason.
+pub fn remove_empty(root_path: &Path, entries: &[EmptyEntry]) -> Vec<(String, io::Error)> {
+    let mut failed = Vec::new();
+    for entry in entries {
+        let path = root_path.join(&entry.path);
+        let result = fs::symlink_metadata(&path).and_then(|metadata| {
+            if entry.is_dir && metadata.is_dir() {
+                fs::remove_dir(&path)
+            } else if !entry.is_dir && metadata.is_file() && metadata.len() == 0 {
+                fs::remove_file(&path)
+            } else {
+                Err(io::Error::other("changed since it was indexed"))
+            }
+        });
+        if let Err(err) = result {
+            failed.push((entry.path.clone(), err));
+        }
+    }
+    failed
+}
@@ -0,0 +1,108 @@
+//! An HTTP server that keeps an indexed tree in memory and answers fuzzy
+//! search queries against it, so each query doesn't re-walk the filesystem.
+//!
+//! - `GET /search?q=<query>&limit=<n>` returns
+//!   `[{"path": ..., "score": ..., "indices": [...]}]`, best matches first.
+//!   `indices` are the matched character positions in the path's last
+//!   segment, for highlighting.
+//! - `POST /reindex` rebuilds the tree and returns its new total size.
+
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use axum::extract::{Query, State};
+use axum::http::StatusCode;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::{Deserialize, Serialize};
+use crate::{fuzzy_search_indices, FileNode, PathStyle, SearchMatch, SearchOptions};
+
+/// Rebuilds the tree for `POST /reindex`.
+pub type Reindex = Box<dyn Fn() -> io::Result<FileNode> + Send + Sync>;
+
+/// How the server renders search results.
+pub struct ServeOptions {
+    pub search_options: SearchOptions,
+    pub path_style: PathStyle,
+    /// The path the tree was indexed from, used to render paths.
+    pub root_path: PathBuf,
+}
+
+struct ServerState {
+    root: RwLock<FileNode>,
+    options: ServeOptions,
+    reindex: Reindex,
+}
+
+#[derive(Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<usize>,
+}
+
+#[derive(Serialize)]
+struct ReindexResult {
+    size: u64,
+    file_count: u64,
+}
+
+/// Serves `root` on `addr` (e.g. `127.0.0.1:8080`) until the process exits.
+pub fn serve(
+    addr: &str,
+    root: FileNode,
+    options: ServeOptions,
+    reindex: Reindex,
+) -> io::Result<()> {
+    let state = Arc::new(ServerState {
+        root: RwLock::new(root),
+        options,
+        reindex,
+    });
+    let app = Router::new()
+        .route("/search", get(search))
+        .route("/reindex", post(reindex_tree))
+        .with_state(state);
+
+    tokio::runtime::Runtime::new()?.block_on(async {
+        let listener = tokio::net::TcpListener::bind(addr).await?;
+        axum::serve(listener, app).await
+    })
+}
+
+async fn search(
+    State(state): State<Arc<ServerState>>,
+    Query(params): Query<SearchParams>,
+) -> Json<Vec<SearchMatch>> {
+    let options = &state.options;
+    let root = state.root.read().unwrap();
+    let mut matches = fuzzy_search_indices(&root, &params.q, &options.search_options);
+    matches.sort_by_key(|found| std::cmp::Reverse(found.score));
+    if let Some(limit) = params.limit {
+        matches.truncate(limit);
+    }
+    for found in &mut matches {
+        found.path = options.search_options.render(
+            &root,
+            options.path_style,
+            &options.root_path,
+            &found.path,
+        );
+    }
+    Json(matches)
+}
+
+async fn reindex_tree(
+    State(state): State<Arc<ServerState>>,
+) -> Result<Json<ReindexResult>, (StatusCode, String)> {
+    let worker = state.clone();
+    let root = tokio::task::spawn_blocking(move || (worker.reindex)())
+        .await
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?
+        .map_err(|err| (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    let result = ReindexResult {
+        size: root.size,
+        file_count: root.file_count,
+    };
+    *state.root.write().unwrap() = root;
+    Ok(Json(result))
+}
@@ -0,0 +1,72 @@
+//! A tree saved together with how it was produced: the fs-index version, the
+//! indexed root, when it was indexed and with which options. Comparing two
+//! saved trees only makes sense if they were indexed alike, with the same
+//! filters and ignore settings, and the metadata makes that checkable.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use serde::{Deserialize, Serialize};
+use crate::{read_json, FileNode};
+
+/// Version of the [`Snapshot`] layout, raised whenever it changes
+/// incompatibly.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// How a tree was produced.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    /// The [`SNAPSHOT_VERSION`] the snapshot was written with.
+    pub snapshot_version: u32,
+    /// Version of the fs-index crate that indexed the tree.
+    pub fs_index_version: String,
+    /// The path the tree was indexed from.
+    pub root: PathBuf,
+    pub indexed_at: SystemTime,
+    /// The options that shaped the tree, e.g. filters and ignore settings,
+    /// as command-line flags in the order they were given.
+    pub options: Vec<String>,
+}
+
+impl RunMetadata {
+    /// Metadata for a tree this version indexed from `root` just now.
+    pub fn new(root: PathBuf, options: Vec<String>) -> Self {
+        RunMetadata {
+            snapshot_version: SNAPSHOT_VERSION,
+            fs_index_version: env!("CARGO_PKG_VERSION").to_string(),
+            root,
+            indexed_at: SystemTime::now(),
+            options,
+        }
+    }
+}
+
+/// A tree and its [`RunMetadata`]. The tree can be borrowed, as in
+/// `Snapshot<&FileNode>`, to write one without cloning it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot<T = FileNode> {
+    pub metadata: RunMetadata,
+    pub tree: T,
+}
+
+/// Reads a tree saved as JSON either bare or as a [`Snapshot`], along with
+/// its metadata if it has any. Compressed files are read like
+/// [`crate::load_tree`] does.
+pub fn load_snapshot(path: &Path) -> io::Result<(FileNode, Option<RunMetadata>)> {
+    let value: serde_json::Value = read_json(path)?;
+    if value.get("metadata").is_none() || value.get("tree").is_none() {
+        return Ok((serde_json::from_value(value)?, None));
+    }
+    let snapshot: Snapshot = serde_json::from_value(value)?;
+    if snapshot.metadata.snapshot_version > SNAPSHOT_VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "{} was written by fs-index {}, which is newer than this one",
+                path.display(),
+                snapshot.metadata.fs_index_version
+            ),
+        ));
+    }
+    Ok((snapshot.tree, Some(snapshot.metadata)))
+}
@@ -0,0 +1,231 @@
+//! Shape statistics of a tree: per-directory file size distributions, to spot
+//! directories of many tiny files that waste blocks and are worth archiving or
+//! packing, where the weight of a tree sits, how deep the tree goes, which
+//! directories hold too many entries and which are the largest, how much
+//! of the total followed symlinks counted twice, and how nodes and sizes
+//! split by node type.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use crate::{FileNode, NodeType};
+
+/// Files smaller than this typically still occupy a whole filesystem block.
+pub const SMALL_FILE_SIZE: u64 = 4096;
+
+/// Size statistics of the files directly inside one directory.
+#[derive(Debug, Clone)]
+pub struct SizeStats {
+    /// Directory path relative to the root, see [`crate::PathStyle::render`].
+    pub path: String,
+    pub files: usize,
+    pub mean: f64,
+    pub median: f64,
+    /// Population standard deviation.
+    pub std_dev: f64,
+    /// Number of files under [`SMALL_FILE_SIZE`].
+    pub small_files: usize,
+}
+
+/// Computes [`SizeStats`] for every directory that directly contains files,
+/// in pre-order. Files in subdirectories count towards those subdirectories
+/// only.
+pub fn fragmentation(root: &FileNode) -> Vec<SizeStats> {
+    root.dirs()
+        .filter_map(|(path, dir)| {
+            let mut sizes: Vec<u64> = dir
+                .children
+                .iter()
+                .filter(|child| matches!(child.node_type, NodeType::File))
+                .map(|child| child.size)
+                .collect();
+            if sizes.is_empty() {
+                return None;
+            }
+            sizes.sort_unstable();
+
+            let files = sizes.len();
+            let mean = sizes.iter().sum::<u64>() as f64 / files as f64;
+            let median = if files.is_multiple_of(2) {
+                (sizes[files / 2 - 1] as f64 + sizes[files / 2] as f64) / 2.0
+            } else {
+                sizes[files / 2] as f64
+            };
+            let variance = sizes
+                .iter()
+                .map(|&size| (size as f64 - mean).powi(2))
+                .sum::<f64>()
+                / files as f64;
+            Some(SizeStats {
+                path,
+                files,
+                mean,
+                median,
+                std_dev: variance.sqrt(),
+                small_files: sizes.iter().filter(|&&size| size < SMALL_FILE_SIZE).count(),
+            })
+        })
+        .collect()
+}
+
+/// The deepest node below `root` and its depth, the number of levels below the
+/// root (so the root's children are at depth 1). The first in pre-order wins
+/// among equally deep nodes. A tree that is only its root gives `("", 0)`.
+pub fn deepest_path(root: &FileNode) -> (String, usize) {
+    let mut deepest = (String::new(), 0);
+    for (path, _) in root.all() {
+        let depth = if path.is_empty() {
+            0
+        } else {
+            path.split('/').count()
+        };
+        if depth > deepest.1 {
+            deepest = (path, depth);
+        }
+    }
+    deepest
+}
+
+/// Directories of at least `threshold` bytes in which no subdirectory holds
+/// more than half of the size, in pre-order. Their weight is their own files
+/// or spread over several subdirectories, so unlike a plain list of the
+/// largest directories, this leaves out ancestors that are only large because
+/// of one big descendant. Ignored and collapsed directories, whose children
+/// are unknown, count as hotspots whenever they are large enough.
+pub fn hotspots(root: &FileNode, threshold: u64) -> Vec<(String, &FileNode)> {
+    root.all()
+        .filter(|(_, node)| {
+            matches!(
+                node.node_type,
+                NodeType::Directory | NodeType::IgnoredDirectory | NodeType::CollapsedDirectory
+            ) && node.size >= threshold
+                && !node.children.iter().any(|child| {
+                    !matches!(child.node_type, NodeType::File) && child.size > node.size / 2
+                })
+        })
+        .collect()
+}
+
+/// Directories with more than `threshold` immediate children, most children
+/// first (ties in pre-order). Such directories are slow to list for the
+/// filesystem and for indexing, and are candidates for sharding. Only stored
+/// children count, so ignored and collapsed directories never qualify.
+pub fn wide_dirs(root: &FileNode, threshold: usize) -> Vec<(String, &FileNode)> {
+    let mut wide: Vec<_> = root
+        .dirs()
+        .filter(|(_, dir)| dir.children.len() > threshold)
+        .collect();
+    wide.sort_by_key(|(_, dir)| std::cmp::Reverse(dir.children.len()));
+    wide
+}
+
+/// The `n` largest directories below the root by total size, largest first
+/// (ties in pre-order). Gitignored and collapsed directories count with their
+/// totals. An ancestor is always at least as large as what is inside it, so a
+/// large directory tends to be listed along with its parents; with
+/// `exclude_ancestors`, a directory inside one already listed is left out
+/// instead, so the directories listed don't overlap.
+pub fn largest_dirs(
+    root: &FileNode,
+    n: usize,
+    exclude_ancestors: bool,
+) -> Vec<(String, &FileNode)> {
+    let mut dirs: Vec<_> = root
+        .all()
+        .filter(|(path, node)| {
+            !path.is_empty()
+                && matches!(
+                    node.node_type,
+                    NodeType::Directory | NodeType::IgnoredDirectory | NodeType::CollapsedDirectory
+                )
+        })
+        .collect();
+    dirs.sort_by_key(|(_, dir)| std::cmp::Reverse(dir.size));
+
+    let mut largest: Vec<(String, &FileNode)> = Vec::new();
+    for (path, dir) in dirs {
+        if largest.len() == n {
+            break;
+        }
+        if exclude_ancestors
+            && largest.iter().any(|(listed, _)| {
+                path.strip_prefix(listed.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+            })
+        {
+            continue;
+        }
+        largest.push((path, dir));
+    }
+    largest
+}
+
+/// How much of a tree's total came in again through followed symlinks, see
+/// [`double_counted`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DoubleCounted {
+    /// Followed symlinks found, not counting those inside a repeated one.
+    pub links: usize,
+    /// Links whose target was already counted, inside the root or through an
+    /// earlier link.
+    pub repeated: usize,
+    /// Total size of what the repeated links lead to.
+    pub size: u64,
+}
+
+/// Finds the followed symlinks whose canonical [`FileNode::link_target`] is
+/// at or below the canonical `root_path` the tree was indexed from, or below
+/// the target of a link met earlier in pre-order, so that their contents
+/// were counted twice. A link into a directory that filters or gitignore
+/// left out is still taken as repeated, and a link to a parent of an earlier
+/// target is not, so the size is approximate. Trees indexed without
+/// following symlinks have no link targets and nothing double counted.
+pub fn double_counted(root: &FileNode, root_path: &Path) -> DoubleCounted {
+    fn visit(node: &FileNode, counted: &mut Vec<PathBuf>, found: &mut DoubleCounted) {
+        for child in &node.children {
+            if let Some(target) = &child.link_target {
+                found.links += 1;
+                if counted.iter().any(|counted| target.starts_with(counted)) {
+                    found.repeated += 1;
+                    found.size += child.size;
+                    continue;
+                }
+                counted.push(target.clone());
+            }
+            visit(child, counted, found);
+        }
+    }
+
+    let mut found = DoubleCounted::default();
+    visit(root, &mut vec![root_path.to_path_buf()], &mut found);
+    found
+}
+
+/// The nodes of one [`NodeType`] in a tree, see [`type_summary`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TypeTotal {
+    pub nodes: u64,
+    /// Size not already counted by another type: the files' own sizes and
+    /// the totals of gitignored and collapsed directories, but nothing for
+    /// directories, whose size is that of what they hold.
+    pub size: u64,
+}
+
+/// How many nodes of each type the tree holds, the root included, and how
+/// much of the total each type accounts for, keyed by [`NodeType::as_str`].
+/// The sizes add up to the root's.
+pub fn type_summary(root: &FileNode) -> BTreeMap<&'static str, TypeTotal> {
+    fn visit(node: &FileNode, totals: &mut BTreeMap<&'static str, TypeTotal>) {
+        let total = totals.entry(node.node_type.as_str()).or_default();
+        total.nodes += 1;
+        if !matches!(node.node_type, NodeType::Directory) {
+            total.size += node.size;
+        }
+        for child in &node.children {
+            visit(child, totals);
+        }
+    }
+
+    let mut totals = BTreeMap::new();
+    visit(root, &mut totals);
+    totals
+}
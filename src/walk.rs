@@ -0,0 +1,51 @@
+//! Lazy pre-order iteration over a tree, yielding each node with its
+//! `/`-separated path relative to the root (empty for the root itself).
+
+use crate::{join_relative, FileNode, NodeType};
+
+/// Iterator returned by [`FileNode::all`].
+#[derive(Debug)]
+pub struct Nodes<'a> {
+    /// Nodes still to visit, the next one last.
+    stack: Vec<(String, &'a FileNode)>,
+}
+
+impl<'a> Iterator for Nodes<'a> {
+    type Item = (String, &'a FileNode);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (path, node) = self.stack.pop()?;
+        self.stack.extend(
+            node.children
+                .iter()
+                .rev()
+                .map(|child| (join_relative(&path, &child.name), child)),
+        );
+        Some((path, node))
+    }
+}
+
+impl FileNode {
+    /// Every node of the tree, this one included, in pre-order. Paths are
+    /// relative to this node; see [`crate::PathStyle::render`] to display
+    /// them.
+    pub fn all(&self) -> Nodes<'_> {
+        Nodes {
+            stack: vec![(String::new(), self)],
+        }
+    }
+
+    /// Every [`NodeType::File`] node of the tree, in pre-order.
+    pub fn files(&self) -> impl Iterator<Item = (String, &FileNode)> {
+        self.all()
+            .filter(|(_, node)| matches!(node.node_type, NodeType::File))
+    }
+
+    /// Every [`NodeType::Directory`] node of the tree, this one included if
+    /// it is a directory, in pre-order. Ignored and collapsed directories,
+    /// whose contents are not in the tree, are left out.
+    pub fn dirs(&self) -> impl Iterator<Item = (String, &FileNode)> {
+        self.all()
+            .filter(|(_, node)| matches!(node.node_type, NodeType::Directory))
+    }
+}
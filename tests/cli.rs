@@ -0,0 +1,655 @@
+//! Runs the fs-index binary on small trees built in temporary directories.
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Output};
+use serde_json::Value;
+
+/// Runs fs-index in `dir` with `args`, ignoring any config file.
+fn fs_index(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fs-index"))
+        .current_dir(dir)
+        .arg("--no-config")
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// The JSON tree fs-index writes to stdout for `args`.
+fn index_json(dir: &Path, args: &[&str]) -> Value {
+    let output = fs_index(dir, &[args, &["--output", "-"]].concat());
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    serde_json::from_slice(&output.stdout).unwrap()
+}
+
+/// The child of `node` named `name`.
+fn child<'a>(node: &'a Value, name: &str) -> &'a Value {
+    node["children"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|child| child["name"] == name)
+        .unwrap_or_else(|| panic!("no child {} in {}", name, node["name"]))
+}
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+#[test]
+fn anchored_parent_rules_apply_to_a_relative_folder() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    write(&repo.join(".gitignore"), "/src/gen/\n");
+    write(&repo.join("src/gen/out.rs"), "generated");
+    write(&repo.join("src/main.rs"), "fn main() {}");
+
+    let src = repo.join("src");
+    for (cwd, folder) in [
+        (dir.path(), "repo/src"),
+        (repo.as_path(), "src"),
+        (src.as_path(), "."),
+        (dir.path(), src.to_str().unwrap()),
+    ] {
+        let root = index_json(cwd, &[folder]);
+        assert_eq!(root["name"], "src", "indexing {}", folder);
+        assert_eq!(
+            child(&root, "gen")["node_type"],
+            "IgnoredDirectory",
+            "indexing {}",
+            folder
+        );
+        assert_eq!(child(&root, "main.rs")["node_type"], "File");
+    }
+}
+
+#[test]
+fn prune_empty_keeps_directories_holding_ignored_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(&root.join(".gitignore"), "*.log\n");
+    write(&root.join("logs/run.log"), "started");
+    write(&root.join("empty-file/zero"), "");
+    fs::create_dir(root.join("empty-dir")).unwrap();
+
+    let output = fs_index(root, &[".", "--prune-empty", "--output", "/dev/null"]);
+    let listed = String::from_utf8_lossy(&output.stdout);
+    assert!(listed.contains("would remove\tempty-dir"), "{}", listed);
+    assert!(!listed.contains("logs"), "{}", listed);
+
+    let output = fs_index(
+        root,
+        &[".", "--prune-empty", "--apply", "--output", "/dev/null"],
+    );
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stdout)
+    );
+    assert!(root.join("logs/run.log").exists());
+    assert!(!root.join("empty-file").exists());
+    assert!(!root.join("empty-dir").exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn strict_fails_on_an_unreadable_entry() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("data/a.txt"), "abc");
+    // Stat'ing a link to itself fails with ELOOP whoever runs the test, where
+    // a permission denied would not when run as root.
+    std::os::unix::fs::symlink("loop", dir.path().join("data/loop")).unwrap();
+
+    let root = index_json(dir.path(), &["."]);
+    assert_eq!(root["size"], 3);
+
+    let output = fs_index(dir.path(), &[".", "--strict", "--output", "-"]);
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(error.contains("data/loop"), "{}", error);
+}
+
+/// The `path` of every node of a JSON tree, in pre-order.
+fn json_paths(node: &Value, paths: &mut Vec<String>) {
+    if let Some(path) = node["path"].as_str() {
+        paths.push(path.to_string());
+    }
+    for child in node["children"].as_array().into_iter().flatten() {
+        json_paths(child, paths);
+    }
+}
+
+#[test]
+fn paths_policy_applies_to_every_output() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = fs::canonicalize(dir.path()).unwrap();
+    write(&root.join("src/main.rs"), "fn main() {}");
+    let absolute = |relative: &str| format!("{}{}", root.display(), relative);
+    let name = root.file_name().unwrap().to_string_lossy().into_owned();
+
+    for (policy, expected, in_tree) in [
+        (
+            "relative",
+            [".".to_string(), "src".into(), "src/main.rs".into()],
+            true,
+        ),
+        (
+            "absolute",
+            [absolute(""), absolute("/src"), absolute("/src/main.rs")],
+            true,
+        ),
+        ("name", [name, "src".into(), "main.rs".into()], false),
+    ] {
+        let tree = index_json(&root, &[".", "--paths", policy]);
+        let mut paths = Vec::new();
+        json_paths(&tree, &mut paths);
+        if in_tree {
+            assert_eq!(paths, expected, "json with {}", policy);
+        } else {
+            assert!(paths.is_empty(), "json with {}", policy);
+        }
+
+        let output = fs_index(
+            &root,
+            &[".", "--paths", policy, "--format", "csv", "--output", "-"],
+        );
+        let csv = String::from_utf8_lossy(&output.stdout);
+        let rows: Vec<_> = csv
+            .lines()
+            .skip(1)
+            .map(|row| row.split(',').next().unwrap())
+            .collect();
+        assert_eq!(rows, expected, "csv with {}", policy);
+
+        let output = fs_index(
+            &root,
+            &[
+                ".", "--paths", policy, "--format", "ndjson", "--output", "-",
+            ],
+        );
+        let rows: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(|row| {
+                serde_json::from_str::<Value>(row).unwrap()["path"]
+                    .as_str()
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(rows, expected, "ndjson with {}", policy);
+
+        let output = fs_index(
+            &root,
+            &[
+                ".",
+                "--paths",
+                policy,
+                "--search",
+                "main",
+                "--output",
+                "/dev/null",
+            ],
+        );
+        let search = String::from_utf8_lossy(&output.stdout);
+        let expected_search = format!("Fuzzy search results for 'main': [{:?}]", expected[2]);
+        assert!(
+            search.contains(&expected_search),
+            "search with {}: {}",
+            policy,
+            search
+        );
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn unreadable_root_exits_with_permission_denied() {
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::process::CommandExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path().join("locked");
+    write(&root.join("a.txt"), "abc");
+    fs::set_permissions(dir.path(), fs::Permissions::from_mode(0o755)).unwrap();
+    fs::set_permissions(&root, fs::Permissions::from_mode(0o000)).unwrap();
+
+    let mut command = Command::new(env!("CARGO_BIN_EXE_fs-index"));
+    command
+        .current_dir(dir.path())
+        .args(["--no-config", "locked", "--output", "-"]);
+    // Root reads the folder whatever its mode, so drop to nobody for the run.
+    // SAFETY: geteuid has no preconditions.
+    if unsafe { libc::geteuid() } == 0 {
+        command.uid(65534).gid(65534);
+    }
+    let output = command.output().unwrap();
+    fs::set_permissions(&root, fs::Permissions::from_mode(0o755)).unwrap();
+
+    assert_eq!(output.status.code(), Some(77));
+    assert!(output.stdout.is_empty());
+    let error = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        error.contains("permission denied reading locked"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn exclude_vcs_drops_vcs_directories_at_any_depth() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(&root.join(".git/HEAD"), "ref: refs/heads/main");
+    write(&root.join("vendor/lib/.hg/store"), "hg");
+    write(&root.join(".github/ci.yml"), "on: push");
+    write(&root.join("vendor/lib/lib.rs"), "");
+
+    let names = |tree: &Value| {
+        let mut paths = Vec::new();
+        json_paths(tree, &mut paths);
+        paths
+    };
+    let all = names(&index_json(root, &["."]));
+    assert!(all.contains(&".git/HEAD".to_string()), "{:?}", all);
+    assert!(
+        all.contains(&"vendor/lib/.hg/store".to_string()),
+        "{:?}",
+        all
+    );
+
+    let kept = names(&index_json(root, &[".", "--exclude-vcs"]));
+    assert!(
+        !kept
+            .iter()
+            .any(|path| path.contains(".git/") || path.ends_with(".git") || path.contains(".hg")),
+        "{:?}",
+        kept
+    );
+    assert!(kept.contains(&".github/ci.yml".to_string()), "{:?}", kept);
+    assert!(
+        kept.contains(&"vendor/lib/lib.rs".to_string()),
+        "{:?}",
+        kept
+    );
+}
+
+#[test]
+fn ext_keeps_matching_files_and_recomputes_sizes() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(&root.join("a.png"), "1234");
+    write(&root.join("notes.txt"), "not an image");
+    write(&root.join("img/b.PNG"), "1234");
+    write(&root.join("img/readme.md"), "about the images");
+    write(&root.join("img/deep/c.png"), "1234");
+    write(&root.join("docs/guide.txt"), "no images here");
+
+    for ext in ["png", ".png", "PNG,jpg"] {
+        let tree = index_json(root, &[".", "--ext", ext]);
+        let mut paths = Vec::new();
+        json_paths(&tree, &mut paths);
+        assert_eq!(
+            paths,
+            [
+                ".",
+                "a.png",
+                "img",
+                "img/b.PNG",
+                "img/deep",
+                "img/deep/c.png"
+            ],
+            "--ext {}",
+            ext
+        );
+        assert_eq!(tree["size"], 12, "--ext {}", ext);
+        assert_eq!(tree["file_count"], 3, "--ext {}", ext);
+        let img = child(&tree, "img");
+        assert_eq!(img["size"], 8);
+        assert_eq!(img["file_count"], 2);
+        assert_eq!(child(img, "deep")["size"], 4);
+    }
+}
+
+#[test]
+fn later_ignore_files_override_earlier_ones_in_the_same_directory() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(&root.join(".gitignore"), "*.log\n");
+    write(&root.join(".fsindexignore"), "!keep.log\n*.tmp\n");
+    for name in ["keep.log", "other.log", "scratch.tmp", "a.txt"] {
+        write(&root.join(name), "x");
+    }
+    let files = |args: &[&str]| {
+        let mut paths = Vec::new();
+        json_paths(&index_json(root, &[&["."], args].concat()), &mut paths);
+        paths.retain(|path| !path.starts_with('.'));
+        paths.sort();
+        paths
+    };
+
+    assert_eq!(files(&[]), ["a.txt", "scratch.tmp"]);
+    assert_eq!(
+        files(&[
+            "--ignore-file",
+            ".gitignore",
+            "--ignore-file",
+            ".fsindexignore"
+        ]),
+        ["a.txt", "keep.log"]
+    );
+    assert_eq!(
+        files(&["--ignore-file", ".fsindexignore"]),
+        ["a.txt", "keep.log", "other.log"]
+    );
+}
+
+#[test]
+fn single_file_root_is_one_file_node() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("one.txt"), "hello");
+
+    let tree = index_json(dir.path(), &["one.txt"]);
+    assert_eq!(tree["name"], "one.txt");
+    assert_eq!(tree["node_type"], "File");
+    assert_eq!(tree["size"], 5);
+    assert_eq!(tree["file_count"], 1);
+    assert!(tree["children"].as_array().is_none_or(Vec::is_empty));
+
+    let output = fs_index(
+        dir.path(),
+        &["one.txt", "--search", "one", "--output", "/dev/null"],
+    );
+    assert!(output.status.success());
+    let report = String::from_utf8_lossy(&output.stdout);
+    assert!(report.contains("Total size: 5 B"), "{}", report);
+    assert!(report.contains("File count: 1"), "{}", report);
+    assert!(!report.contains("Max depth"), "{}", report);
+    assert!(
+        report.contains("Not searching: the root is a single file"),
+        "{}",
+        report
+    );
+}
+
+#[test]
+fn git_root_paths_are_relative_to_the_repository() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    write(&repo.join("src/util/a.rs"), "");
+    let src = repo.join("src");
+
+    for (cwd, folder, expected) in [
+        (
+            src.as_path(),
+            ".",
+            ["src", "src/util", "src/util/a.rs"].as_slice(),
+        ),
+        (src.as_path(), "util", &["src/util", "src/util/a.rs"]),
+        (dir.path(), "repo/src/util", &["src/util", "src/util/a.rs"]),
+    ] {
+        let tree = index_json(cwd, &[folder, "--paths", "git-root"]);
+        let mut paths = Vec::new();
+        json_paths(&tree, &mut paths);
+        assert_eq!(
+            paths,
+            expected,
+            "indexing {} from {}",
+            folder,
+            cwd.display()
+        );
+    }
+
+    // At the repository root itself, paths are relative to it as usual.
+    let tree = index_json(&repo, &[".", "--paths", "git-root"]);
+    assert_eq!(tree["path"], ".");
+    assert_eq!(child(&tree, "src")["path"], "src");
+}
+
+#[test]
+fn dedup_min_size_leaves_out_small_duplicates() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(&root.join("big/a.bin"), "0123456789");
+    write(&root.join("big/b.bin"), "0123456789");
+    write(&root.join("small/a.txt"), "abc");
+    write(&root.join("small/b.txt"), "abc");
+
+    let report = |min_size: &[&str]| {
+        let args = [
+            &[".", "--find-duplicates", "--output", "/dev/null"],
+            min_size,
+        ]
+        .concat();
+        let output = fs_index(root, &args);
+        assert!(output.status.success());
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let all = report(&[]);
+    assert!(
+        all.contains("  big/a.bin") && all.contains("  small/a.txt"),
+        "{}",
+        all
+    );
+    let large = report(&["--dedup-min-size", "4B"]);
+    assert!(large.contains("  big/a.bin"), "{}", large);
+    assert!(!large.contains("  small/"), "{}", large);
+}
+
+#[test]
+fn glob_path_matches_the_relative_path_instead_of_the_name() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("src/util/mod.rs"), "");
+    write(&dir.path().join("tests/mod.rs"), "");
+
+    let listed = |args: &[&str]| {
+        let args = [&[".", "--output", "/dev/null"], args].concat();
+        let output = fs_index(dir.path(), &args);
+        assert!(output.status.success());
+        let mut lines: Vec<_> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| line.ends_with("mod.rs") && !line.contains(' '))
+            .map(str::to_string)
+            .collect();
+        lines.sort();
+        lines
+    };
+    assert_eq!(
+        listed(&["--glob", "mod.rs"]),
+        ["src/util/mod.rs", "tests/mod.rs"]
+    );
+    assert!(listed(&["--glob", "src/*.rs"]).is_empty());
+    assert_eq!(
+        listed(&["--glob", "src/*.rs", "--glob-path"]),
+        ["src/util/mod.rs"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn cache_is_not_reused_across_tree_shaping_options() {
+    // A Symlink node's type is an object holding its target.
+    let kind = |node: &Value| match &node["node_type"] {
+        Value::Object(fields) => fields.keys().next().unwrap().clone(),
+        node_type => node_type.as_str().unwrap().to_string(),
+    };
+    // Each cold run is without the parent .gitignore: an ignored directory
+    // in its tree would keep it from being reused whatever the key.
+    let cold = ["--no-parent-ignore"];
+    for (warm, name, node_type) in [
+        (
+            &["--no-parent-ignore", "--max-depth", "1"][..],
+            "a",
+            "CollapsedDirectory",
+        ),
+        (
+            &["--no-parent-ignore", "--no-follow-symlinks"],
+            "link",
+            "Symlink",
+        ),
+        (&[], "skip", "IgnoredDirectory"),
+    ] {
+        let dir = tempfile::tempdir().unwrap();
+        let base = dir.path();
+        fs::create_dir(base.join(".git")).unwrap();
+        write(&base.join(".gitignore"), "/t/skip/\n");
+        write(&base.join("t/a/b/c.txt"), "hi");
+        write(&base.join("t/skip/x"), "x");
+        std::os::unix::fs::symlink("a", base.join("t/link")).unwrap();
+        let run = |options: &[&str]| {
+            index_json(base, &[&["t", "--cache", "cache.json"], options].concat())
+        };
+
+        let tree = run(&cold);
+        assert_ne!(kind(child(&tree, name)), node_type, "{:?}", warm);
+        let tree = run(warm);
+        assert_eq!(kind(child(&tree, name)), node_type, "{:?}", warm);
+    }
+}
+
+#[test]
+fn loaded_trees_refuse_reading_or_deleting_files() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    write(&root.join("t/empty/zero"), "");
+    let output = fs_index(root, &["t", "--output", "tree.json"]);
+    assert!(output.status.success());
+    // A directory named like the loaded root, which its files would be
+    // resolved against.
+    fs::create_dir_all(root.join("elsewhere/t/empty")).unwrap();
+
+    for args in [
+        &["--prune-empty"][..],
+        &["--prune-empty", "--apply"],
+        &["--find-duplicates"],
+    ] {
+        let output = fs_index(
+            &root.join("elsewhere"),
+            &[&["--load", "../tree.json"], args].concat(),
+        );
+        let usage = String::from_utf8_lossy(&output.stdout);
+        assert!(usage.starts_with("Usage:"), "{:?}: {}", args, usage);
+    }
+    assert!(root.join("elsewhere/t/empty").exists());
+    assert!(root.join("t/empty/zero").exists());
+}
+
+/// The sorted paths of the files of a JSON tree, leaving out ignore files.
+fn json_files(node: &Value) -> Vec<String> {
+    fn collect(node: &Value, files: &mut Vec<String>) {
+        if node["node_type"] == "File" && node["name"] != ".gitignore" {
+            files.push(node["path"].as_str().unwrap().to_string());
+        }
+        for child in node["children"].as_array().into_iter().flatten() {
+            collect(child, files);
+        }
+    }
+    let mut files = Vec::new();
+    collect(node, &mut files);
+    files.sort();
+    files
+}
+
+#[test]
+fn ignore_rules_apply_as_if_in_a_root_ignore_file() {
+    let dir = tempfile::tempdir().unwrap();
+    let root = dir.path();
+    for path in ["keep.rs", "x.tmp", "a/y.tmp", "build/out", "a/build/out"] {
+        write(&root.join(path), "data");
+    }
+    write(&root.join("a/.gitignore"), "!y.tmp\n");
+    let files = |args: &[&str]| json_files(&index_json(root, &[&["."], args].concat()));
+
+    assert_eq!(
+        files(&[]),
+        ["a/build/out", "a/y.tmp", "build/out", "keep.rs", "x.tmp"]
+    );
+    // Unanchored rules match at any depth, anchored ones only at the top,
+    // and the tree's own ignore files override them.
+    assert_eq!(
+        files(&["--ignore-rules", "*.tmp\n/build/"]),
+        ["a/build/out", "a/y.tmp", "keep.rs"]
+    );
+    assert_eq!(
+        files(&["--ignore-rules", "*.tmp", "--ignore-rules", "build/"]),
+        ["a/y.tmp", "keep.rs"]
+    );
+}
+
+#[test]
+fn manifest_reports_follow_the_paths_policy() {
+    let dir = tempfile::tempdir().unwrap();
+    let base = fs::canonicalize(dir.path()).unwrap();
+    let root = base.join("t");
+    write(&root.join("src/changed.rs"), "old");
+    write(&root.join("src/gone.rs"), "gone");
+    let manifest = base.join("manifest.tsv");
+    let manifest = manifest.to_str().unwrap();
+    let output = fs_index(
+        &root,
+        &[".", "--manifest", manifest, "--output", "/dev/null"],
+    );
+    assert!(output.status.success());
+    write(&root.join("src/changed.rs"), "newer");
+    fs::remove_file(root.join("src/gone.rs")).unwrap();
+    write(&root.join("src/added.rs"), "new");
+
+    let render = |policy, path: &str| match policy {
+        "absolute" => format!("{}/{}", root.display(), path),
+        "name" => path.rsplit('/').next().unwrap().to_string(),
+        _ => path.to_string(),
+    };
+    for policy in ["relative", "absolute", "name"] {
+        for (check, expected) in [
+            (
+                "--verify-manifest",
+                [
+                    ("mismatched", "src/changed.rs"),
+                    ("missing", "src/gone.rs"),
+                    ("new", "src/added.rs"),
+                ],
+            ),
+            (
+                "--check-manifest",
+                [
+                    ("resized", "src/changed.rs"),
+                    ("missing", "src/gone.rs"),
+                    ("new", "src/added.rs"),
+                ],
+            ),
+        ] {
+            let output = fs_index(
+                &root,
+                &[
+                    ".",
+                    "--paths",
+                    policy,
+                    check,
+                    manifest,
+                    "--output",
+                    "/dev/null",
+                ],
+            );
+            assert!(!output.status.success());
+            let report = String::from_utf8_lossy(&output.stdout);
+            for (kind, path) in expected {
+                let line = format!("{}\t{}", kind, render(policy, path));
+                assert!(
+                    report.lines().any(|reported| reported == line),
+                    "{} with {}: no {:?} in {}",
+                    check,
+                    policy,
+                    line,
+                    report
+                );
+            }
+        }
+    }
+}
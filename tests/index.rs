@@ -0,0 +1,387 @@
+//! Indexes small trees built in temporary directories through the library.
+
+use std::fs;
+use std::path::Path;
+use fs_index::{fuzzy_search, FileNode, IndexConfig, NodeType, SearchOptions};
+
+fn write(path: &Path, contents: &str) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    fs::write(path, contents).unwrap();
+}
+
+/// The child of `node` named `name`.
+fn child<'a>(node: &'a FileNode, name: &str) -> &'a FileNode {
+    node.children
+        .iter()
+        .find(|child| child.name == name)
+        .unwrap_or_else(|| panic!("no child {} in {}", name, node.name))
+}
+
+#[test]
+fn root_without_a_last_component_is_named_after_its_target() {
+    let dir = tempfile::tempdir().unwrap();
+    let root_dir = dir.path().join("root");
+    write(&root_dir.join("sub/a.txt"), "abc");
+
+    let root = IndexConfig::new()
+        .parent_ignore(false)
+        .index(&root_dir.join("sub/.."))
+        .unwrap();
+    assert_eq!(root.name, "root");
+    assert_eq!(root.size, 3);
+    assert!(matches!(child(&root, "sub").node_type, NodeType::Directory));
+}
+
+#[test]
+fn anchored_parent_rules_apply_to_a_relative_root() {
+    // Tests run in the package directory, so a directory below the target
+    // directory can be named relative to it.
+    let dir = tempfile::tempdir_in(env!("CARGO_TARGET_TMPDIR")).unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    write(&repo.join(".gitignore"), "/src/gen/\n");
+    write(&repo.join("src/gen/out.rs"), "generated");
+    write(&repo.join("src/main.rs"), "fn main() {}");
+    let cwd = std::env::current_dir().unwrap();
+    let src = repo.join("src");
+    let relative = src.strip_prefix(&cwd).unwrap();
+    assert!(relative.is_relative());
+
+    let config = IndexConfig::new();
+    let expected = config.index(&src).unwrap();
+    for root in [relative.to_path_buf(), relative.join("../src")] {
+        let indexed = config.index(&root).unwrap();
+        assert_eq!(indexed.name, "src");
+        assert!(
+            matches!(child(&indexed, "gen").node_type, NodeType::IgnoredDirectory),
+            "indexing {}",
+            root.display()
+        );
+        assert_eq!(shape(&indexed), shape(&expected));
+        let summary = config.summarize(&root, None).unwrap();
+        assert_eq!(summary.size, expected.size);
+        assert_eq!(summary.file_count, expected.file_count);
+    }
+}
+
+#[test]
+fn intern_names_shares_repeated_names_in_both_walks() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("a/index.js"), "a");
+    write(&dir.path().join("b/index.js"), "b");
+    let shared = |root: &FileNode| {
+        let a = &child(child(root, "a"), "index.js").name;
+        let b = &child(child(root, "b"), "index.js").name;
+        a.as_str().as_ptr() == b.as_str().as_ptr()
+    };
+
+    let config = IndexConfig::new().parent_ignore(false);
+    assert!(!shared(&config.index(dir.path()).unwrap()));
+    let config = config.intern_names(true);
+    assert!(shared(&config.index(dir.path()).unwrap()));
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+
+        let root_dir = fs::File::open(dir.path()).unwrap();
+        assert!(shared(
+            &config.index_dir_fd(root_dir.as_fd(), "root").unwrap()
+        ));
+        let config = IndexConfig::new().parent_ignore(false);
+        assert!(!shared(
+            &config.index_dir_fd(root_dir.as_fd(), "root").unwrap()
+        ));
+    }
+}
+
+/// Names and types of the nodes below `node`, in a stable order.
+fn shape(node: &FileNode) -> Vec<(String, String)> {
+    let mut shape: Vec<_> = node
+        .all()
+        .map(|(path, node)| (path, node.node_type.as_str().to_string()))
+        .collect();
+    shape.sort();
+    shape
+}
+
+#[cfg(unix)]
+#[test]
+fn names_only_descriptor_walk_matches_the_path_walk() {
+    use std::os::fd::AsFd;
+
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join(".gitignore"), "*.log\n");
+    write(&dir.path().join("src/main.rs"), "fn main() {}");
+    write(&dir.path().join("src/debug.log"), "trace");
+    write(&dir.path().join("README.md"), "readme");
+
+    let config = IndexConfig::new().parent_ignore(false).names_only(true);
+    let by_path = config.index(dir.path()).unwrap();
+    let root_dir = fs::File::open(dir.path()).unwrap();
+    let by_fd = config.index_dir_fd(root_dir.as_fd(), "root").unwrap();
+    assert_eq!(shape(&by_fd), shape(&by_path));
+    assert_eq!(by_fd.file_count, 3);
+    assert!(by_fd
+        .files()
+        .all(|(_, file)| file.size == 0 && file.mtime.is_none()));
+}
+
+#[test]
+fn decomposed_names_match_composed_queries_when_normalized() {
+    let dir = tempfile::tempdir().unwrap();
+    // "café" with the accent as a combining character, as macOS stores it.
+    write(&dir.path().join("cafe\u{301}.txt"), "coffee");
+    let config = IndexConfig::new().parent_ignore(false);
+    let options = |normalize_unicode| SearchOptions {
+        normalize_unicode,
+        ..SearchOptions::default()
+    };
+
+    let root = config.index(dir.path()).unwrap();
+    assert!(fuzzy_search(&root, "caf\u{e9}", &options(false)).is_empty());
+
+    let root = config.normalize_unicode(true).index(dir.path()).unwrap();
+    assert_eq!(root.children[0].name, "caf\u{e9}.txt");
+    assert_eq!(
+        fuzzy_search(&root, "caf\u{e9}", &options(true)),
+        ["caf\u{e9}.txt"]
+    );
+}
+
+#[cfg(unix)]
+#[test]
+fn zero_byte_files_count_and_fifos_are_never_opened() {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("empty"), "");
+    write(&dir.path().join("data"), "12345");
+    let fifo = CString::new(dir.path().join("pipe").as_os_str().as_bytes()).unwrap();
+    // SAFETY: `fifo` is a NUL-terminated path.
+    assert_eq!(unsafe { libc::mkfifo(fifo.as_ptr(), 0o644) }, 0);
+
+    // Opening the FIFO would block with no writer, failing the test by
+    // hanging rather than by an assertion.
+    let mut root = IndexConfig::new()
+        .parent_ignore(false)
+        .index(dir.path())
+        .unwrap();
+    root.compute_hashes(dir.path());
+    assert_eq!(root.size, 5);
+    assert_eq!(root.file_count, 2);
+    let empty = child(&root, "empty");
+    assert!(matches!(empty.node_type, NodeType::File));
+    assert_eq!(empty.size, 0);
+    let pipe = child(&root, "pipe");
+    assert!(matches!(pipe.node_type, NodeType::Special));
+    assert_eq!(pipe.size, 0);
+    assert_eq!(pipe.hash, None);
+}
+
+#[test]
+fn gitignored_files_are_left_out_of_the_tree() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join(".gitignore"), "*.log\n");
+    write(&dir.path().join("foo.log"), "12345");
+    write(&dir.path().join("a.txt"), "ab");
+    write(&dir.path().join("sub/bar.log"), "xyz");
+    write(&dir.path().join("keep/.gitignore"), "!x.log\n");
+    write(&dir.path().join("keep/x.log"), "x");
+
+    let config = IndexConfig::new().parent_ignore(false);
+    let root = config.index(dir.path()).unwrap();
+    let mut files: Vec<_> = root.files().map(|(path, _)| path).collect();
+    files.sort();
+    assert_eq!(
+        files,
+        [".gitignore", "a.txt", "keep/.gitignore", "keep/x.log"]
+    );
+    assert_eq!(root.file_count, 4);
+    assert_eq!(child(&root, "sub").size, 0);
+    #[cfg(unix)]
+    {
+        use std::os::fd::AsFd;
+
+        let root_dir = fs::File::open(dir.path()).unwrap();
+        let by_fd = config.index_dir_fd(root_dir.as_fd(), "root").unwrap();
+        assert_eq!(shape(&by_fd), shape(&root));
+    }
+}
+
+#[test]
+fn root_matched_by_a_parent_gitignore_is_still_indexed() {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = dir.path().join("repo");
+    fs::create_dir_all(repo.join(".git")).unwrap();
+    write(&repo.join(".gitignore"), "target\n");
+    write(&repo.join("target/debug/app"), "binary");
+
+    let root = IndexConfig::new().index(&repo.join("target")).unwrap();
+    assert_eq!(root.name, "target");
+    assert!(matches!(root.node_type, NodeType::Directory));
+    assert_eq!(root.size, 6);
+    let debug = child(&root, "debug");
+    assert!(matches!(debug.node_type, NodeType::Directory));
+    assert_eq!(child(debug, "app").size, 6);
+}
+
+#[cfg(unix)]
+#[test]
+fn relative_symlinks_record_their_resolved_absolute_path() {
+    use std::os::unix::fs::symlink;
+    use std::path::PathBuf;
+
+    let dir = tempfile::tempdir().unwrap();
+    let base = fs::canonicalize(dir.path()).unwrap();
+    let root_dir = base.join("root");
+    write(&root_dir.join("data/file.txt"), "abc");
+    write(&base.join("outside.txt"), "out");
+    fs::create_dir(root_dir.join("links")).unwrap();
+    symlink("../data/file.txt", root_dir.join("links/inside")).unwrap();
+    symlink("../../outside.txt", root_dir.join("links/outside")).unwrap();
+    symlink("missing.txt", root_dir.join("links/broken")).unwrap();
+
+    let root = IndexConfig::new()
+        .parent_ignore(false)
+        .follow_symlinks(false)
+        .index(&root_dir)
+        .unwrap();
+    let links = child(&root, "links");
+    let link = |name: &str| match &child(links, name).node_type {
+        NodeType::Symlink { target, resolved } => (target.clone(), resolved.clone()),
+        other => panic!("{} is {:?}", name, other),
+    };
+    assert_eq!(
+        link("inside"),
+        (
+            PathBuf::from("../data/file.txt"),
+            Some(root_dir.join("data/file.txt"))
+        )
+    );
+    assert_eq!(
+        link("outside"),
+        (
+            PathBuf::from("../../outside.txt"),
+            Some(base.join("outside.txt"))
+        )
+    );
+    assert_eq!(link("broken"), (PathBuf::from("missing.txt"), None));
+}
+
+/// Writes `root` as JSON to `path`, gzip or zstd compressed.
+#[cfg(feature = "compression")]
+fn write_compressed(root: &FileNode, path: &Path, gzip: bool) {
+    let file = fs::File::create(path).unwrap();
+    if gzip {
+        let mut encoder = flate2::write::GzEncoder::new(file, flate2::Compression::default());
+        serde_json::to_writer(&mut encoder, root).unwrap();
+        encoder.finish().unwrap();
+    } else {
+        let mut encoder = zstd::Encoder::new(file, 0).unwrap();
+        serde_json::to_writer(&mut encoder, root).unwrap();
+        encoder.finish().unwrap();
+    }
+}
+
+#[test]
+fn compressed_trees_load_back_unchanged() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("tree/src/main.rs"), "fn main() {}");
+    write(&dir.path().join("tree/README.md"), "readme");
+    let root = IndexConfig::new()
+        .parent_ignore(false)
+        .index(&dir.path().join("tree"))
+        .unwrap();
+    let plain = dir.path().join("file_tree.json");
+    fs::write(&plain, serde_json::to_vec(&root).unwrap()).unwrap();
+    let expected = serde_json::to_value(&root).unwrap();
+    let loaded = fs_index::load_tree(&plain).unwrap();
+    assert_eq!(serde_json::to_value(&loaded).unwrap(), expected);
+
+    #[cfg(feature = "compression")]
+    for (name, gzip) in [("file_tree.json.gz", true), ("tree.bin", false)] {
+        // The second name has no telling extension: only the magic bytes
+        // say it is zstd.
+        let path = dir.path().join(name);
+        write_compressed(&root, &path, gzip);
+        let loaded = fs_index::load_tree(&path).unwrap();
+        assert_eq!(serde_json::to_value(&loaded).unwrap(), expected, "{}", name);
+    }
+    #[cfg(not(feature = "compression"))]
+    {
+        // gzip's magic bytes followed by anything: it is refused before
+        // being decompressed.
+        let path = dir.path().join("file_tree.json.gz");
+        fs::write(&path, [0x1f, 0x8b, 0x08, 0x00]).unwrap();
+        let err = fs_index::load_tree(&path).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::Unsupported);
+        assert!(err.to_string().contains("gzip compressed"), "{}", err);
+    }
+}
+
+#[test]
+fn duplicates_below_the_minimum_size_are_not_reported() {
+    let dir = tempfile::tempdir().unwrap();
+    write(&dir.path().join("big/a.bin"), "0123456789");
+    write(&dir.path().join("big/b.bin"), "0123456789");
+    write(&dir.path().join("small/a.txt"), "abc");
+    write(&dir.path().join("small/b.txt"), "abc");
+    write(&dir.path().join("empty/a"), "");
+    write(&dir.path().join("empty/b"), "");
+    let root = IndexConfig::new()
+        .parent_ignore(false)
+        .index(dir.path())
+        .unwrap();
+    let duplicates = |min_size| {
+        let mut groups = fs_index::find_duplicate_files(&root, dir.path(), min_size);
+        groups.iter_mut().for_each(|group| group.sort());
+        groups
+    };
+
+    let big = vec!["big/a.bin".to_string(), "big/b.bin".into()];
+    let small = vec!["small/a.txt".to_string(), "small/b.txt".into()];
+    let empty = vec!["empty/a".to_string(), "empty/b".into()];
+    assert_eq!(duplicates(4), std::slice::from_ref(&big));
+    assert_eq!(duplicates(10), std::slice::from_ref(&big));
+    assert!(duplicates(11).is_empty());
+    assert_eq!(
+        duplicates(fs_index::DEFAULT_DEDUP_MIN_SIZE),
+        [big.clone(), small.clone()]
+    );
+    assert_eq!(duplicates(0), [big, small, empty]);
+}
+
+#[test]
+fn glob_search_matches_names_or_full_paths() {
+    let dir = tempfile::tempdir().unwrap();
+    for path in [
+        "src/main.rs",
+        "src/util/mod.rs",
+        "tests/main.rs",
+        "README.md",
+    ] {
+        write(&dir.path().join(path), "");
+    }
+    let root = IndexConfig::new()
+        .parent_ignore(false)
+        .index(dir.path())
+        .unwrap();
+    let glob = |pattern, full_path| {
+        let mut matches = fs_index::glob_search(&root, pattern, full_path).unwrap();
+        matches.sort();
+        matches
+    };
+
+    assert_eq!(glob("main.rs", false), ["src/main.rs", "tests/main.rs"]);
+    assert_eq!(
+        glob("*.rs", false),
+        ["src/main.rs", "src/util/mod.rs", "tests/main.rs"]
+    );
+    // A name never holds a `/`, so a pattern with one only matches paths.
+    assert!(glob("src/*.rs", false).is_empty());
+    assert_eq!(glob("src/*.rs", true), ["src/main.rs", "src/util/mod.rs"]);
+    assert_eq!(glob("src/**/mod.rs", true), ["src/util/mod.rs"]);
+    assert!(glob("main.rs", true).is_empty());
+    assert!(fs_index::glob_search(&root, "[", false).is_err());
+}